@@ -8,6 +8,8 @@ use crate::compositor_thread::{
 };
 #[cfg(feature = "gl")]
 use crate::gl;
+#[cfg(feature = "software-compositor")]
+use crate::swgl;
 use crate::touch::{TouchAction, TouchHandler};
 use crate::windowing::{
     self, EmbedderCoordinates, MouseWindowEvent, WebRenderDebugOption, WindowMethods,
@@ -20,7 +22,7 @@ use euclid::{Point2D, Rect, Scale, Vector2D};
 use fnv::{FnvHashMap, FnvHashSet};
 use gfx_traits::{Epoch, FontData};
 #[cfg(feature = "gl")]
-use image::{DynamicImage, ImageFormat};
+use image::{DynamicImage, ImageFormat, RgbImage};
 use ipc_channel::ipc;
 use libc::c_void;
 use log::warn;
@@ -42,19 +44,25 @@ use script_traits::{
 use servo_geometry::{DeviceIndependentPixel, FramebufferUintLength};
 use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::fs::{create_dir_all, File};
+use std::io;
 use std::io::Write;
+use std::mem;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
 use std::rc::Rc;
 use style_traits::{CSSPixel, DevicePixel, PinchZoomFactor};
 use time::{now, precise_time_ns, precise_time_s};
 use webrender_api::units::{
-    DeviceIntPoint, DeviceIntSize, DevicePoint, LayoutPoint, LayoutVector2D, WorldPoint,
+    DeviceIntPoint, DeviceIntSize, DevicePoint, LayoutPoint, LayoutTransform, LayoutVector2D,
+    WorldPoint,
 };
 use webrender_api::{
-    self, BuiltDisplayList, CaptureBits, DirtyRect, DocumentId, Epoch as WebRenderEpoch,
-    ExternalScrollId, HitTestFlags, PipelineId as WebRenderPipelineId, RenderApi, ScrollClamping,
-    ScrollLocation, Transaction, ZoomFactor,
+    self, BuiltDisplayList, CaptureBits, DebugCommand, DirtyRect, DocumentId, DynamicProperties,
+    Epoch as WebRenderEpoch, ExternalScrollId, HitTestFlags, PipelineId as WebRenderPipelineId,
+    PropertyBindingId, PropertyBindingKey, PropertyValue, RenderApi, RenderReasons,
+    ScrollClamping, ScrollLocation, Transaction, ZoomFactor,
 };
 use webrender_surfman::WebrenderSurfman;
 
@@ -74,6 +82,11 @@ enum NotReadyToPaint {
 const MAX_ZOOM: f32 = 8.0;
 const MIN_ZOOM: f32 = 0.1;
 
+/// How long to coalesce scroll/zoom events before flushing them into a single
+/// WebRender transaction, so a fast scroll wheel or trackpad doesn't make us
+/// issue (and wait on) a transaction per input event.
+const SCROLL_COMPOSITE_TIMEOUT_NS: u64 = 40_000_000; // 40ms
+
 trait ConvertPipelineIdFromWebRender {
     fn from_webrender(&self) -> PipelineId;
 }
@@ -178,6 +191,28 @@ pub struct IOCompositor<Window: WindowMethods + ?Sized> {
     /// Whether we're waiting on a recomposite after dispatching a scroll.
     waiting_for_results_of_scroll: bool,
 
+    /// Recent timestamped scroll displacement samples, used to estimate a release
+    /// velocity for kinetic scrolling. Cleared once a gesture ends (whether or not it
+    /// was fast enough to start a fling).
+    scroll_velocity_samples: Vec<(u64, LayoutVector2D)>,
+
+    /// The in-progress momentum scroll ("fling"), if any.
+    fling: Option<FlingState>,
+
+    /// The in-progress smooth scroll animation (e.g. from Home/End), if any.
+    scroll_animation: Option<ScrollAnimation>,
+
+    /// The `RenderReasons` passed to `generate_frame()` since the last composite, used
+    /// to report why we're compositing in the time-profiler output.
+    accumulated_render_reasons: RenderReasons,
+
+    /// In-flight and completed asynchronous screenshots, keyed by the handle returned
+    /// from `request_screenshot`.
+    async_screenshots: FnvHashMap<AsyncScreenshotHandle, AsyncScreenshotState>,
+
+    /// Monotonically increasing counter used to mint new `AsyncScreenshotHandle`s.
+    next_async_screenshot_handle: u32,
+
     /// Used by the logic that determines when it is safe to output an
     /// image for the reftest framework.
     ready_to_save_state: ReadyState,
@@ -197,6 +232,19 @@ pub struct IOCompositor<Window: WindowMethods + ?Sized> {
     /// The GL bindings for webrender
     webrender_gl: Rc<dyn gleam::gl::Gl>,
 
+    /// Which backend `composite_specific_target` should rasterize through.
+    backend: CompositorBackend,
+
+    /// The on-disk shader program cache, if one was configured. `None` disables
+    /// program caching entirely.
+    program_cache: Option<ProgramCache>,
+
+    /// Whether we've already written the program cache back to disk since startup
+    /// or since the last `clear_program_cache`. The cache is saved after the first
+    /// successful frame (and again after the first frame following a clear); there's
+    /// no point re-saving it every frame after that.
+    program_cache_saved: bool,
+
     /// Some XR devices want to run on the main thread.
     pub webxr_main_thread: webxr::MainThreadRegistry,
 
@@ -242,12 +290,71 @@ struct ScrollZoomEvent {
     cursor: DeviceIntPoint,
     /// The number of OS events that have been coalesced together into this one event.
     event_count: u32,
+    /// Whether this event was synthesized by an in-progress fling, rather than coming
+    /// directly from an input device. Used to tell whether hitting a scroll clamp should
+    /// cancel the fling.
+    is_fling: bool,
+}
+
+/// How long a scroll displacement sample remains part of the release-velocity estimate.
+const SCROLL_VELOCITY_SAMPLE_WINDOW_NS: u64 = 100_000_000; // 100ms
+
+/// The minimum release speed, in layout pixels per nanosecond, for a scroll gesture to
+/// start a fling. (Roughly 300 CSS px/s.)
+const FLING_START_VELOCITY: f32 = 0.0000003;
+
+/// Once a fling's speed drops below this, in layout pixels per nanosecond, it is
+/// considered finished. (Roughly 30 CSS px/s.)
+const FLING_MIN_VELOCITY: f32 = 0.00000003;
+
+/// Exponential decay applied to a fling's velocity once per second, i.e.
+/// `velocity(t + dt) = velocity(t) * FLING_FRICTION_PER_SECOND.powf(dt)` with `dt` in
+/// seconds.
+const FLING_FRICTION_PER_SECOND: f32 = 0.015;
+
+/// An in-progress momentum scroll, synthesizing decaying scroll deltas each frame until
+/// its velocity drops below [FLING_MIN_VELOCITY].
+#[derive(Clone, Copy)]
+struct FlingState {
+    /// Current velocity, in layout pixels per nanosecond.
+    velocity: LayoutVector2D,
+    /// The last time (as returned by `precise_time_ns()`) this fling was ticked.
+    last_tick: u64,
+    /// The cursor position to scroll at, as with [ScrollZoomEvent::cursor].
+    cursor: DeviceIntPoint,
+}
+
+/// How long a keyboard-triggered smooth scroll (Home/End) takes to reach
+/// its target offset.
+const SMOOTH_SCROLL_DURATION_NS: u64 = 250_000_000; // 250ms
+
+/// An in-progress animated scroll to a fixed target offset, used to smoothly animate
+/// `ScrollLocation::Start`/`ScrollLocation::End` (and, in future, page up/down) instead
+/// of jumping there instantly.
+#[derive(Clone, Copy)]
+struct ScrollAnimation {
+    pipeline_id: PipelineId,
+    external_id: ExternalScrollId,
+    start_offset: LayoutVector2D,
+    target_offset: LayoutVector2D,
+    start_time: u64,
+}
+
+/// Cubic ease-out: starts fast and eases into the target, i.e. `1 - (1-t)^3`.
+fn ease_out_cubic(t: f32) -> f32 {
+    let inv = 1.0 - t.clamp(0.0, 1.0);
+    1.0 - inv * inv * inv
 }
 
 #[derive(Debug, PartialEq)]
 enum CompositionRequest {
     NoCompositingNecessary,
     CompositeNow(CompositingReason),
+    /// A scroll or zoom event has arrived, but we are deliberately holding off
+    /// compositing until this deadline (in nanoseconds, as returned by
+    /// `precise_time_ns()`) so that a burst of input events coalesces into a
+    /// single WebRender transaction instead of one transaction per event.
+    CompositeOnScrollTimeout(u64),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -257,6 +364,345 @@ pub enum ShutdownState {
     FinishedShuttingDown,
 }
 
+/// A simple cubic-bezier-style timing function for a [CompositorAnimation]. Covers the
+/// keyword and `cubic-bezier()` timing functions that layout hands us for transform and
+/// opacity animations; anything more exotic (e.g. `steps()`) still round-trips through
+/// script as before.
+#[derive(Clone, Copy, Debug)]
+enum CompositorTimingFunction {
+    Linear,
+    CubicBezier(f32, f32, f32, f32),
+}
+
+impl CompositorTimingFunction {
+    /// Evaluate this timing function at `progress` in `[0.0, 1.0]`, returning the eased
+    /// progress to interpolate with.
+    fn at(&self, progress: f32) -> f32 {
+        match *self {
+            CompositorTimingFunction::Linear => progress,
+            CompositorTimingFunction::CubicBezier(x1, y1, x2, y2) => {
+                solve_cubic_bezier(x1, y1, x2, y2, progress)
+            },
+        }
+    }
+}
+
+/// Solve a `cubic-bezier(x1, y1, x2, y2)` easing curve for `t` via a handful of Newton's
+/// method iterations, falling back to bisection if the derivative is too flat.
+fn solve_cubic_bezier(x1: f32, y1: f32, x2: f32, y2: f32, t: f32) -> f32 {
+    fn sample(a1: f32, a2: f32, t: f32) -> f32 {
+        let c = 3.0 * a1;
+        let b = 3.0 * (a2 - a1) - c;
+        let a = 1.0 - c - b;
+        ((a * t + b) * t + c) * t
+    }
+    fn sample_derivative(a1: f32, a2: f32, t: f32) -> f32 {
+        let c = 3.0 * a1;
+        let b = 3.0 * (a2 - a1) - c;
+        let a = 1.0 - c - b;
+        (3.0 * a * t + 2.0 * b) * t + c
+    }
+
+    let mut x = t;
+    for _ in 0..8 {
+        let x_at_t = sample(x1, x2, x) - t;
+        if x_at_t.abs() < 0.0001 {
+            break;
+        }
+        let derivative = sample_derivative(x1, x2, x);
+        if derivative.abs() < 0.000001 {
+            // Too flat for Newton's method to make progress; bisect on [0, 1] instead.
+            let (mut lo, mut hi) = (0.0, 1.0);
+            x = t;
+            for _ in 0..32 {
+                let x_at_x = sample(x1, x2, x);
+                if (x_at_x - t).abs() < 0.0001 {
+                    break;
+                }
+                if x_at_x < t {
+                    lo = x;
+                } else {
+                    hi = x;
+                }
+                x = (lo + hi) * 0.5;
+            }
+            break;
+        }
+        x -= x_at_t / derivative;
+    }
+    sample(y1, y2, x)
+}
+
+/// Interpolate between two transform matrices for a compositor-sampled transform
+/// animation. Naively lerping the matrix components directly produces visible
+/// "popping" once rotation is involved, so instead we decompose each matrix into
+/// translation/scale/rotation, interpolate those independently (rotation via
+/// quaternion slerp), and recompose. Shear and perspective are assumed to be
+/// identity, which holds for the transform lists layout hands the compositor.
+fn interpolate_transform(
+    from: &LayoutTransform,
+    to: &LayoutTransform,
+    progress: f32,
+) -> LayoutTransform {
+    let from = decompose_transform(from);
+    let to = decompose_transform(to);
+
+    let translation = (
+        lerp(from.translation.0, to.translation.0, progress),
+        lerp(from.translation.1, to.translation.1, progress),
+        lerp(from.translation.2, to.translation.2, progress),
+    );
+    let scale = (
+        lerp(from.scale.0, to.scale.0, progress),
+        lerp(from.scale.1, to.scale.1, progress),
+        lerp(from.scale.2, to.scale.2, progress),
+    );
+    let rotation = slerp_quaternion(from.rotation, to.rotation, progress);
+
+    recompose_transform(translation, rotation, scale)
+}
+
+#[inline]
+fn lerp(from: f32, to: f32, progress: f32) -> f32 {
+    from + (to - from) * progress
+}
+
+/// The translation/scale/rotation components of a 3D transform matrix, decomposed so
+/// that they can be interpolated independently. See `interpolate_transform`.
+struct DecomposedTransform {
+    translation: (f32, f32, f32),
+    scale: (f32, f32, f32),
+    /// A unit quaternion, as `(x, y, z, w)`.
+    rotation: (f32, f32, f32, f32),
+}
+
+fn decompose_transform(transform: &LayoutTransform) -> DecomposedTransform {
+    let translation = (transform.m41, transform.m42, transform.m43);
+
+    let scale_x = vec3_len((transform.m11, transform.m12, transform.m13));
+    let scale_y = vec3_len((transform.m21, transform.m22, transform.m23));
+    let scale_z = vec3_len((transform.m31, transform.m32, transform.m33));
+
+    let row0 = vec3_normalize((transform.m11, transform.m12, transform.m13), scale_x);
+    let row1 = vec3_normalize((transform.m21, transform.m22, transform.m23), scale_y);
+    let row2 = vec3_normalize((transform.m31, transform.m32, transform.m33), scale_z);
+
+    DecomposedTransform {
+        translation,
+        scale: (scale_x, scale_y, scale_z),
+        rotation: matrix_to_quaternion(row0, row1, row2),
+    }
+}
+
+fn recompose_transform(
+    translation: (f32, f32, f32),
+    rotation: (f32, f32, f32, f32),
+    scale: (f32, f32, f32),
+) -> LayoutTransform {
+    let (row0, row1, row2) = quaternion_to_matrix(rotation);
+
+    LayoutTransform::new(
+        row0.0 * scale.0,
+        row0.1 * scale.0,
+        row0.2 * scale.0,
+        0.0,
+        row1.0 * scale.1,
+        row1.1 * scale.1,
+        row1.2 * scale.1,
+        0.0,
+        row2.0 * scale.2,
+        row2.1 * scale.2,
+        row2.2 * scale.2,
+        0.0,
+        translation.0,
+        translation.1,
+        translation.2,
+        1.0,
+    )
+}
+
+fn vec3_len(v: (f32, f32, f32)) -> f32 {
+    (v.0 * v.0 + v.1 * v.1 + v.2 * v.2).sqrt()
+}
+
+fn vec3_normalize(v: (f32, f32, f32), len: f32) -> (f32, f32, f32) {
+    if len == 0.0 {
+        v
+    } else {
+        (v.0 / len, v.1 / len, v.2 / len)
+    }
+}
+
+/// Build a unit quaternion `(x, y, z, w)` from an orthonormal rotation matrix given as
+/// its three rows.
+fn matrix_to_quaternion(
+    row0: (f32, f32, f32),
+    row1: (f32, f32, f32),
+    row2: (f32, f32, f32),
+) -> (f32, f32, f32, f32) {
+    let trace = row0.0 + row1.1 + row2.2;
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        (
+            (row1.2 - row2.1) / s,
+            (row2.0 - row0.2) / s,
+            (row0.1 - row1.0) / s,
+            0.25 * s,
+        )
+    } else if row0.0 > row1.1 && row0.0 > row2.2 {
+        let s = (1.0 + row0.0 - row1.1 - row2.2).sqrt() * 2.0;
+        (
+            0.25 * s,
+            (row0.1 + row1.0) / s,
+            (row2.0 + row0.2) / s,
+            (row1.2 - row2.1) / s,
+        )
+    } else if row1.1 > row2.2 {
+        let s = (1.0 + row1.1 - row0.0 - row2.2).sqrt() * 2.0;
+        (
+            (row0.1 + row1.0) / s,
+            0.25 * s,
+            (row1.2 + row2.1) / s,
+            (row2.0 - row0.2) / s,
+        )
+    } else {
+        let s = (1.0 + row2.2 - row0.0 - row1.1).sqrt() * 2.0;
+        (
+            (row2.0 + row0.2) / s,
+            (row1.2 + row2.1) / s,
+            0.25 * s,
+            (row0.1 - row1.0) / s,
+        )
+    }
+}
+
+/// Convert a unit quaternion `(x, y, z, w)` back into a rotation matrix, given as its
+/// three rows.
+fn quaternion_to_matrix(
+    q: (f32, f32, f32, f32),
+) -> ((f32, f32, f32), (f32, f32, f32), (f32, f32, f32)) {
+    let (x, y, z, w) = q;
+    (
+        (
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + z * w),
+            2.0 * (x * z - y * w),
+        ),
+        (
+            2.0 * (x * y - z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + x * w),
+        ),
+        (
+            2.0 * (x * z + y * w),
+            2.0 * (y * z - x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ),
+    )
+}
+
+/// Spherically interpolate between two unit quaternions, falling back to a normalized
+/// linear interpolation when they're nearly identical (where slerp's divisor blows up).
+fn slerp_quaternion(
+    a: (f32, f32, f32, f32),
+    b: (f32, f32, f32, f32),
+    t: f32,
+) -> (f32, f32, f32, f32) {
+    let dot = a.0 * b.0 + a.1 * b.1 + a.2 * b.2 + a.3 * b.3;
+    let (b, dot) = if dot < 0.0 {
+        ((-b.0, -b.1, -b.2, -b.3), -dot)
+    } else {
+        (b, dot)
+    };
+
+    if dot > 0.9995 {
+        let result = (
+            lerp(a.0, b.0, t),
+            lerp(a.1, b.1, t),
+            lerp(a.2, b.2, t),
+            lerp(a.3, b.3, t),
+        );
+        let len = vec3_len((result.0, result.1, result.2)).hypot(result.3);
+        return if len == 0.0 {
+            result
+        } else {
+            (result.0 / len, result.1 / len, result.2 / len, result.3 / len)
+        };
+    }
+
+    let theta_0 = dot.acos();
+    let theta = theta_0 * t;
+    let sin_theta = theta.sin();
+    let sin_theta_0 = theta_0.sin();
+
+    let s0 = (theta_0 - theta).sin() / sin_theta_0;
+    let s1 = sin_theta / sin_theta_0;
+    (
+        a.0 * s0 + b.0 * s1,
+        a.1 * s0 + b.1 * s1,
+        a.2 * s0 + b.2 * s1,
+        a.3 * s0 + b.3 * s1,
+    )
+}
+
+/// Stitch a sequence of `(scroll_offset_in_css_px, band_image)` pairs, captured while
+/// scrolling the page from top to bottom, into a single image covering the full
+/// scrollable height. Bands are assumed to be full-width and to overlap somewhat at
+/// their edges; later bands win in the overlap region, since they reflect a scroll
+/// position at least as far down the page.
+fn stitch_bands(bands: &[(f32, Image)], page_height: f32, device_pixels_per_page_px: f32) -> Image {
+    let width = bands.first().map_or(0, |(_, image)| image.width);
+    let height = (page_height * device_pixels_per_page_px).round() as u32;
+    let bytes_per_pixel = 3;
+    let stride = width as usize * bytes_per_pixel;
+    let mut bytes = vec![0u8; stride * height as usize];
+
+    for (offset, image) in bands {
+        let dest_y0 = (*offset * device_pixels_per_page_px).round() as u32;
+        for row in 0..image.height {
+            let dest_y = dest_y0 + row;
+            if dest_y >= height {
+                break;
+            }
+            let src_start = row as usize * stride;
+            let dest_start = dest_y as usize * stride;
+            bytes[dest_start..dest_start + stride]
+                .copy_from_slice(&image.bytes[src_start..src_start + stride]);
+        }
+    }
+
+    Image {
+        width,
+        height,
+        format: PixelFormat::RGB8,
+        bytes: ipc::IpcSharedMemory::from_bytes(&bytes),
+        id: None,
+        cors_status: CorsStatus::Safe,
+    }
+}
+
+/// The animatable value(s) a single [CompositorAnimation] interpolates between.
+#[derive(Clone, Copy, Debug)]
+enum AnimatedPropertyValue {
+    Transform(LayoutTransform, LayoutTransform),
+    Opacity(f32, f32),
+}
+
+/// A transform or opacity animation sampled directly by the compositor, so that simple
+/// CSS animations and transitions keep advancing even while script is busy. Layout sends
+/// us one of these per active animation, keyed by the WebRender property-binding id it
+/// was created with; we interpolate a value for it on every composite instead of waiting
+/// for script to tick and re-send a new display list.
+#[derive(Clone, Copy, Debug)]
+struct CompositorAnimation {
+    /// When this animation started, in the same units as `precise_time_ns()`.
+    start_time: u64,
+    /// How long this animation runs for, in nanoseconds.
+    duration: u64,
+    timing_function: CompositorTimingFunction,
+    values: AnimatedPropertyValue,
+}
+
 struct PipelineDetails {
     /// The pipeline associated with this PipelineDetails object.
     pipeline: Option<CompositionPipeline>,
@@ -277,6 +723,22 @@ struct PipelineDetails {
     /// The compositor-side [ScrollTree]. This is used to allow finding and scrolling
     /// nodes in the compositor before forwarding new offsets to WebRender.
     scroll_tree: ScrollTree,
+
+    /// Transform and opacity animations that this compositor is sampling directly,
+    /// keyed by the WebRender property-binding id they were registered with.
+    compositor_animations: FnvHashMap<PropertyBindingId, CompositorAnimation>,
+
+    /// The epoch of the most recent display list submitted for this pipeline, whether
+    /// or not WebRender has finished scene-building it yet. Used to let a hit test
+    /// targeting this pipeline wait only on this pipeline's own scene, rather than
+    /// flushing scene building for every pipeline in the document.
+    requested_epoch: Option<WebRenderEpoch>,
+
+    /// The epoch WebRender had actually rendered for this pipeline as of the most
+    /// recent composite, so a reftest/screenshot harness can confirm the frame it's
+    /// about to capture reflects a specific, already-submitted layout without racing
+    /// the constellation handshake in `is_ready_to_paint_image_output`.
+    rendered_epoch: Option<Epoch>,
 }
 
 impl PipelineDetails {
@@ -288,6 +750,9 @@ impl PipelineDetails {
             visible: true,
             hit_test_items: Vec::new(),
             scroll_tree: ScrollTree::default(),
+            compositor_animations: FnvHashMap::default(),
+            requested_epoch: None,
+            rendered_epoch: None,
         }
     }
 
@@ -325,6 +790,88 @@ enum CompositeTarget {
 
     /// Compose to a PNG, write it to disk, and then exit the browser (used for reftests)
     PngFile,
+
+    /// Scroll through the page in viewport-height bands, compositing and stitching each
+    /// one, and return a single PNG spanning the page's full scrollable height.
+    FullPage,
+}
+
+/// Which backend `composite_specific_target` rasterizes through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompositorBackend {
+    /// Render through the live GL context bound to `webrender_surfman`, and present
+    /// the result to the native window the normal way.
+    Gl,
+    /// Rasterize WebRender's display list on worker threads into a CPU-backed
+    /// framebuffer instead of touching a GL context. There is no window to present
+    /// to, so only the PNG-producing `CompositeTarget`s are meaningful; used on
+    /// machines with no usable GPU (CI, servers, containers).
+    Software,
+}
+
+/// A persistent on-disk cache of compiled GL shader program binaries, so that a
+/// fresh process start doesn't have to pay the full WebRender shader-compilation
+/// cost before the first `composite()`. Keyed by GL renderer/driver version plus
+/// the WebRender revision, so a driver upgrade naturally misses the cache instead
+/// of handing back a binary the new driver might reject.
+struct ProgramCache {
+    directory: PathBuf,
+}
+
+impl ProgramCache {
+    fn new(directory: PathBuf) -> Self {
+        ProgramCache { directory }
+    }
+
+    fn key_for(gl: &dyn gleam::gl::Gl) -> String {
+        let renderer = gl.get_string(gleam::gl::RENDERER);
+        let driver_version = gl.get_string(gleam::gl::VERSION);
+        format!(
+            "{}-{}-{}",
+            renderer, driver_version, webrender::WEBRENDER_RECORDING_VERSION
+        )
+    }
+
+    fn path_for(&self, gl: &dyn gleam::gl::Gl) -> PathBuf {
+        self.directory.join(format!("{}.bin", Self::key_for(gl)))
+    }
+}
+
+/// Which region `Msg::CreatePng` should capture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PngCaptureKind {
+    /// Capture only what is currently visible in the framebuffer.
+    Viewport,
+    /// Scroll through the whole page and stitch the result into one full-height image.
+    FullPage,
+}
+
+/// How `Msg::CapturePixels` should encode the composited frame it reads back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CaptureImageFormat {
+    /// Encode as PNG.
+    Png,
+    /// Encode as JPEG.
+    Jpeg,
+    /// Don't encode at all; hand back the raw RGB8 pixels as-is.
+    Raw,
+}
+
+/// A handle to an asynchronous screenshot requested with `request_screenshot`, used to
+/// poll for its result later with `map_screenshot`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AsyncScreenshotHandle(u32);
+
+/// The state of one asynchronous screenshot, from request to completion.
+enum AsyncScreenshotState {
+    /// Requested, but no composite has happened yet to kick off the GPU readback.
+    Requested(Option<Rect<f32, CSSPixel>>),
+    /// The framebuffer region has been copied into a pixel-buffer object, which may or
+    /// may not have landed on the CPU side yet.
+    #[cfg(feature = "gl")]
+    Pending(gl::PixelBufferHandle),
+    /// The pixel-buffer object has landed and the image is ready to hand back.
+    Ready(Image),
 }
 
 impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
@@ -332,6 +879,8 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         window: Rc<Window>,
         state: InitialCompositorState,
         output_file: Option<String>,
+        backend: CompositorBackend,
+        program_cache_dir: Option<PathBuf>,
         is_running_problem_test: bool,
         exit_after_load: bool,
         convert_mouse_to_touch: bool,
@@ -356,6 +905,12 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             touch_handler: TouchHandler::new(),
             pending_scroll_zoom_events: Vec::new(),
             waiting_for_results_of_scroll: false,
+            scroll_velocity_samples: Vec::new(),
+            fling: None,
+            scroll_animation: None,
+            accumulated_render_reasons: RenderReasons::empty(),
+            async_screenshots: FnvHashMap::default(),
+            next_async_screenshot_handle: 0,
             composite_target,
             shutdown_state: ShutdownState::NotShuttingDown,
             page_zoom: Scale::new(1.0),
@@ -373,6 +928,9 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             webrender_api: state.webrender_api,
             webrender_surfman: state.webrender_surfman,
             webrender_gl: state.webrender_gl,
+            backend,
+            program_cache: program_cache_dir.map(ProgramCache::new),
+            program_cache_saved: false,
             webxr_main_thread: state.webxr_main_thread,
             pending_paint_metrics: HashMap::new(),
             cursor: Cursor::None,
@@ -389,6 +947,8 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         window: Rc<Window>,
         state: InitialCompositorState,
         output_file: Option<String>,
+        backend: CompositorBackend,
+        program_cache_dir: Option<PathBuf>,
         is_running_problem_test: bool,
         exit_after_load: bool,
         convert_mouse_to_touch: bool,
@@ -398,14 +958,19 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             window,
             state,
             output_file,
+            backend,
+            program_cache_dir,
             is_running_problem_test,
             exit_after_load,
             convert_mouse_to_touch,
             top_level_browsing_context_id,
         );
 
-        // Make sure the GL state is OK
-        compositor.assert_gl_framebuffer_complete();
+        // Make sure the GL state is OK. The software backend has no GL context to check.
+        if compositor.backend == CompositorBackend::Gl {
+            compositor.assert_gl_framebuffer_complete();
+            compositor.load_program_cache();
+        }
 
         // Set the size of the root layer.
         compositor.update_zoom_transform();
@@ -499,8 +1064,40 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                 self.touch_handler.on_event_processed(result);
             },
 
-            (Msg::CreatePng(rect, reply), ShutdownState::NotShuttingDown) => {
-                let res = self.composite_specific_target(CompositeTarget::WindowAndPng, rect);
+            (Msg::CaptureWebRender { path, bits }, ShutdownState::NotShuttingDown) => {
+                self.capture_webrender_to(path, bits);
+            },
+
+            (Msg::ReplayWebRender(path), ShutdownState::NotShuttingDown) => {
+                self.replay_webrender(path);
+            },
+
+            (
+                Msg::ArePipelineEpochsRendered(expected_epochs, reply),
+                ShutdownState::NotShuttingDown,
+            ) => {
+                let rendered = self.pipelines_rendered_to_epochs(&expected_epochs);
+                if let Err(e) = reply.send(rendered) {
+                    warn!(
+                        "Sending reply to are-pipeline-epochs-rendered failed ({:?}).",
+                        e
+                    );
+                }
+            },
+
+            (
+                Msg::SetCompositorAnimations(pipeline_id, animations),
+                ShutdownState::NotShuttingDown,
+            ) => {
+                self.set_compositor_animations(pipeline_id, animations);
+            },
+
+            (Msg::CreatePng(rect, capture_kind, reply), ShutdownState::NotShuttingDown) => {
+                let target = match capture_kind {
+                    PngCaptureKind::Viewport => CompositeTarget::WindowAndPng,
+                    PngCaptureKind::FullPage => CompositeTarget::FullPage,
+                };
+                let res = self.composite_specific_target(target, rect);
                 if let Err(ref e) = res {
                     info!("Error retrieving PNG: {:?}", e);
                 }
@@ -510,6 +1107,13 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                 }
             },
 
+            (Msg::CapturePixels(format, reply), ShutdownState::NotShuttingDown) => {
+                let bytes = self.capture_composited_frame(format);
+                if let Err(e) = reply.send(bytes) {
+                    warn!("Sending reply to capture pixels failed ({:?}).", e);
+                }
+            },
+
             (Msg::IsReadyToSaveImageReply(is_ready), ShutdownState::NotShuttingDown) => {
                 assert_eq!(
                     self.ready_to_save_state,
@@ -653,7 +1257,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
 
                 let mut txn = Transaction::new();
                 txn.scroll_node_with_id(point, scroll_id, clamping);
-                txn.generate_frame();
+                self.generate_frame(&mut txn, RenderReasons::SCROLL);
                 self.webrender_api
                     .send_transaction(self.webrender_document, txn);
             },
@@ -671,6 +1275,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                     let details = self.pipeline_details(PipelineId::from_webrender(pipeline_id));
                     details.hit_test_items = display_list_info.hit_test_info;
                     details.install_new_scroll_tree(display_list_info.scroll_tree);
+                    details.requested_epoch = Some(display_list_info.epoch);
 
                     let mut txn = Transaction::new();
                     txn.set_display_list(
@@ -684,7 +1289,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                         ),
                         true,
                     );
-                    txn.generate_frame();
+                    self.generate_frame(&mut txn, RenderReasons::APPLICATION);
                     self.webrender_api
                         .send_transaction(self.webrender_document, txn);
                 },
@@ -699,15 +1304,12 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             )) => {
                 // When a display list is sent to WebRender, it starts scene building in a
                 // separate thread and then that display list is available for hit testing.
-                // Without flushing scene building, any hit test we do might be done against
-                // a previous scene, if the last one we sent hasn't finished building.
-                //
-                // TODO(mrobinson): Flushing all scene building is a big hammer here, because
-                // we might only be interested in a single pipeline. The only other option
-                // would be to listen to the TransactionNotifier for previous per-pipeline
-                // transactions, but that isn't easily compatible with the event loop wakeup
-                // mechanism from libserver.
-                self.webrender_api.flush_scene_builder();
+                // Without waiting for scene building to finish, any hit test we do might be
+                // done against a previous scene, if the last one we sent hasn't finished
+                // building. Rather than flushing scene building for every pipeline, only
+                // wait on the pipeline the hit test actually targets (or the root, for an
+                // untargeted hit test against the whole document).
+                self.wait_for_pipeline_epoch_to_build(pipeline.map(PipelineId::from_webrender));
 
                 let result = self.hit_test_at_point_with_flags_and_pipeline(point, flags, pipeline);
                 let _ = sender.send(result);
@@ -864,7 +1466,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         let pipeline_id = frame_tree.pipeline.id.to_webrender();
         let mut txn = Transaction::new();
         txn.set_root_pipeline(pipeline_id);
-        txn.generate_frame();
+        self.generate_frame(&mut txn, RenderReasons::APPLICATION);
         self.webrender_api
             .send_transaction(self.webrender_document, txn);
 
@@ -1005,8 +1607,12 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
     }
 
     fn hit_test_at_device_point(&self, point: DevicePoint) -> Option<CompositorHitTestResult> {
+        // WebRender's world space composes page zoom, pinch zoom, and the hidpi factor
+        // together, so all three need to come out of the incoming device point or hit
+        // testing (and the `point_in_viewport` handed back to script) will be wrong
+        // while pinch-zoomed.
         let dppx = self.page_zoom * self.hidpi_factor();
-        let scaled_point = (point / dppx).to_untyped();
+        let scaled_point = (point / dppx / self.pinch_zoom_level()).to_untyped();
         let world_point = WorldPoint::from_untyped(scaled_point);
         return self.hit_test_at_point(world_point);
     }
@@ -1018,6 +1624,42 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             .cloned();
     }
 
+    /// Block until WebRender has finished building the scene for `pipeline_id`'s most
+    /// recently-submitted display list (or, if `pipeline_id` is `None`, the root
+    /// pipeline's), pumping compositor messages in the meantime. This is the targeted
+    /// alternative to `flush_scene_builder()`, which would wait on every pipeline in
+    /// the document instead of just the one a hit test cares about.
+    fn wait_for_pipeline_epoch_to_build(&mut self, pipeline_id: Option<PipelineId>) {
+        let pipeline_id = match pipeline_id.or(self.root_pipeline.id) {
+            Some(pipeline_id) => pipeline_id,
+            None => return,
+        };
+
+        let requested_epoch = match self.pipeline_details.get(&pipeline_id) {
+            Some(details) => details.requested_epoch,
+            None => return,
+        };
+        let requested_epoch = match requested_epoch {
+            Some(epoch) => epoch,
+            // We've never submitted a display list for this pipeline; there's nothing
+            // to wait for.
+            None => return,
+        };
+
+        let webrender_pipeline_id = pipeline_id.to_webrender();
+        while self.shutdown_state != ShutdownState::ShuttingDown {
+            let built_epoch = self
+                .webrender
+                .current_epoch(self.webrender_document, webrender_pipeline_id);
+            if built_epoch == Some(requested_epoch) {
+                return;
+            }
+
+            let msg = self.port.recv_compositor_msg();
+            self.handle_browser_message(msg);
+        }
+    }
+
     fn hit_test_at_point_with_flags_and_pipeline(
         &self,
         point: WorldPoint,
@@ -1126,6 +1768,8 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
     }
 
     fn on_touch_down(&mut self, identifier: TouchId, point: DevicePoint) {
+        // A new touch takes over control of the scroll; don't let a previous fling fight it.
+        self.fling = None;
         self.touch_handler.on_touch_down(identifier, point);
         self.send_touch_event(TouchEventType::Down, identifier, point);
     }
@@ -1145,7 +1789,9 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                     )),
                     cursor: cursor,
                     event_count: 1,
+                    is_fling: false,
                 });
+                self.request_scroll_composite();
             },
             TouchAction::DispatchEvent => {
                 self.send_touch_event(TouchEventType::Move, identifier, point);
@@ -1157,8 +1803,9 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
     fn on_touch_up(&mut self, identifier: TouchId, point: DevicePoint) {
         self.send_touch_event(TouchEventType::Up, identifier, point);
 
-        if let TouchAction::Click = self.touch_handler.on_touch_up(identifier, point) {
-            self.simulate_mouse_click(point);
+        match self.touch_handler.on_touch_up(identifier, point) {
+            TouchAction::Click => self.simulate_mouse_click(point),
+            _ => self.maybe_start_fling(point.cast()),
         }
     }
 
@@ -1188,23 +1835,184 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         phase: TouchEventType,
     ) {
         match phase {
-            TouchEventType::Move => self.on_scroll_window_event(delta, cursor),
-            TouchEventType::Up | TouchEventType::Cancel => {
+            TouchEventType::Down => {
+                self.fling = None;
                 self.on_scroll_window_event(delta, cursor);
             },
-            TouchEventType::Down => {
+            TouchEventType::Move => self.on_scroll_window_event(delta, cursor),
+            TouchEventType::Up | TouchEventType::Cancel => {
                 self.on_scroll_window_event(delta, cursor);
+                self.maybe_start_fling(cursor);
             },
         }
     }
 
     fn on_scroll_window_event(&mut self, scroll_location: ScrollLocation, cursor: DeviceIntPoint) {
+        if let ScrollLocation::Delta(delta) = scroll_location {
+            self.track_scroll_velocity_sample(delta);
+        }
         self.pending_scroll_zoom_events.push(ScrollZoomEvent {
             magnification: 1.0,
             scroll_location: scroll_location,
             cursor: cursor,
             event_count: 1,
+            is_fling: false,
         });
+        self.request_scroll_composite();
+    }
+
+    /// Record a timestamped scroll displacement sample, for estimating a release
+    /// velocity to carry into a fling. Samples older than
+    /// `SCROLL_VELOCITY_SAMPLE_WINDOW_NS` are dropped so a pause mid-gesture doesn't
+    /// poison the estimate with stale, possibly much faster, early samples.
+    fn track_scroll_velocity_sample(&mut self, delta: LayoutVector2D) {
+        let now = precise_time_ns();
+        self.scroll_velocity_samples
+            .retain(|&(time, _)| now.saturating_sub(time) <= SCROLL_VELOCITY_SAMPLE_WINDOW_NS);
+        self.scroll_velocity_samples.push((now, delta));
+    }
+
+    /// If the tracked scroll velocity at release is fast enough, start a fling:
+    /// synthesized scroll deltas that decay over time and feed into the same
+    /// coalescing/`scroll_node_with_id` pipeline as real scroll events. Either way,
+    /// the velocity history is cleared so the next gesture starts fresh.
+    fn maybe_start_fling(&mut self, cursor: DeviceIntPoint) {
+        let samples = mem::take(&mut self.scroll_velocity_samples);
+        if samples.len() < 2 {
+            return;
+        }
+
+        let (earliest_time, _) = samples[0];
+        let (latest_time, _) = samples[samples.len() - 1];
+        let elapsed_ns = latest_time.saturating_sub(earliest_time);
+        if elapsed_ns == 0 {
+            return;
+        }
+
+        let total_delta: LayoutVector2D = samples
+            .iter()
+            .skip(1)
+            .fold(LayoutVector2D::zero(), |acc, &(_, delta)| acc + delta);
+        let velocity = total_delta / (elapsed_ns as f32);
+
+        if vec3_len((velocity.x, velocity.y, 0.0)) < FLING_START_VELOCITY {
+            return;
+        }
+
+        self.fling = Some(FlingState {
+            velocity,
+            last_tick: precise_time_ns(),
+            cursor,
+        });
+
+        // Treat the fling like any other animation so the compositor keeps scheduling
+        // frames for it even once the gesture that started it is long over.
+        self.composite_if_necessary(CompositingReason::Animation);
+    }
+
+    /// Advance any in-progress fling by one frame: decay its velocity, synthesize the
+    /// corresponding scroll delta, and feed it into the normal scroll pipeline. Ends the
+    /// fling once its velocity drops below `FLING_MIN_VELOCITY`.
+    fn tick_fling(&mut self) {
+        let mut fling = match self.fling.take() {
+            Some(fling) => fling,
+            None => return,
+        };
+
+        let now = precise_time_ns();
+        let dt_ns = now.saturating_sub(fling.last_tick);
+        if dt_ns == 0 {
+            self.fling = Some(fling);
+            return;
+        }
+        let dt_seconds = dt_ns as f32 / 1_000_000_000.0;
+
+        let delta = fling.velocity * (dt_ns as f32);
+        fling.velocity = fling.velocity * FLING_FRICTION_PER_SECOND.powf(dt_seconds);
+        fling.last_tick = now;
+
+        if vec3_len((fling.velocity.x, fling.velocity.y, 0.0)) < FLING_MIN_VELOCITY {
+            // This is the fling's last tick; don't put it back.
+        } else {
+            self.fling = Some(fling);
+            // Keep the animation loop running so the next tick is scheduled even if
+            // nothing else currently has a pending composite.
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+
+        self.pending_scroll_zoom_events.push(ScrollZoomEvent {
+            magnification: 1.0,
+            scroll_location: ScrollLocation::Delta(delta),
+            cursor: fling.cursor,
+            event_count: 1,
+            is_fling: true,
+        });
+        self.request_scroll_composite();
+    }
+
+    /// Advance the in-progress smooth-scroll animation (if any) by one frame: compute
+    /// the eased offset for the current time, push it directly to the scroll tree and
+    /// WebRender, and finish exactly at `target_offset` once the duration has elapsed.
+    fn tick_scroll_animation(&mut self) {
+        let animation = match self.scroll_animation {
+            Some(animation) => animation,
+            None => return,
+        };
+
+        let now = precise_time_ns();
+        let elapsed_ns = now.saturating_sub(animation.start_time);
+        let t = elapsed_ns as f32 / SMOOTH_SCROLL_DURATION_NS as f32;
+        let progress = ease_out_cubic(t);
+
+        let offset = animation.start_offset +
+            (animation.target_offset - animation.start_offset) * progress;
+
+        if let Some(details) = self.pipeline_details.get_mut(&animation.pipeline_id) {
+            for node in details.scroll_tree.nodes.iter_mut() {
+                if node.external_id() == Some(animation.external_id) {
+                    node.set_offset(offset);
+                }
+            }
+        }
+
+        let mut txn = Transaction::new();
+        let scroll_origin = LayoutPoint::new(-offset.x, -offset.y);
+        txn.scroll_node_with_id(
+            scroll_origin,
+            animation.external_id,
+            ScrollClamping::NoClamping,
+        );
+        self.generate_frame(&mut txn, RenderReasons::SCROLL);
+        self.webrender_api
+            .send_transaction(self.webrender_document, txn);
+
+        self.send_scroll_positions_to_layout_for_pipeline(&animation.pipeline_id);
+
+        if t >= 1.0 {
+            self.scroll_animation = None;
+        } else {
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+    }
+
+    /// Call `txn.generate_frame(reasons)` and fold `reasons` into
+    /// `accumulated_render_reasons`, so that whatever we log at the next composite
+    /// reflects every reason a frame was generated since then, not just the last one.
+    fn generate_frame(&mut self, txn: &mut Transaction, reasons: RenderReasons) {
+        txn.generate_frame(reasons);
+        self.accumulated_render_reasons.insert(reasons);
+    }
+
+    /// Arrange for the next burst of coalesced scroll/zoom events to be flushed
+    /// once `SCROLL_COMPOSITE_TIMEOUT_NS` has elapsed, rather than composited
+    /// immediately. Does nothing if a composite is already pending or in flight,
+    /// so that further events arriving mid-burst just keep coalescing.
+    fn request_scroll_composite(&mut self) {
+        if self.composition_request == CompositionRequest::NoCompositingNecessary {
+            self.composition_request = CompositionRequest::CompositeOnScrollTimeout(
+                precise_time_ns() + SCROLL_COMPOSITE_TIMEOUT_NS,
+            );
+        }
     }
 
     fn process_pending_scroll_events(&mut self) {
@@ -1232,6 +2040,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                         )),
                         cursor: this_cursor,
                         event_count: 1,
+                        is_fling: scroll_event.is_fling,
                     })
                 },
                 &mut Some(ref mut last_combined_event) => {
@@ -1248,6 +2057,8 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                         );
                     }
                     last_combined_event.magnification *= scroll_event.magnification;
+                    last_combined_event.is_fling =
+                        last_combined_event.is_fling || scroll_event.is_fling;
                 },
             }
         }
@@ -1272,12 +2083,79 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                 None => return,
             };
 
+            let is_jump_to_extent =
+                matches!(scroll_location, ScrollLocation::Start | ScrollLocation::End);
+
             if let Some(details) = self.pipeline_details.get_mut(&result.pipeline_id) {
+                if is_jump_to_extent {
+                    // Peek the node's current offset (a zero delta moves nothing) so we
+                    // know where to animate from, then resolve the jump itself to find out
+                    // where we're animating to.
+                    let current = details
+                        .scroll_tree
+                        .scroll_node_or_ancestor(&result.scroll_tree_node, ScrollLocation::Delta(LayoutVector2D::zero()));
+                    let target = details
+                        .scroll_tree
+                        .scroll_node_or_ancestor(&result.scroll_tree_node, scroll_location);
+
+                    if let (Some((_, start_offset)), Some((external_id, target_offset))) =
+                        (current, target)
+                    {
+                        if start_offset != target_offset {
+                            // Put the node back where it started; `tick_scroll_animation`
+                            // will walk it to `target_offset` over several frames instead
+                            // of jumping there within this single transaction.
+                            for node in details.scroll_tree.nodes.iter_mut() {
+                                if node.external_id() == Some(external_id) {
+                                    node.set_offset(start_offset);
+                                }
+                            }
+                            self.scroll_animation = Some(ScrollAnimation {
+                                pipeline_id: result.pipeline_id,
+                                external_id,
+                                start_offset,
+                                target_offset,
+                                start_time: precise_time_ns(),
+                            });
+                            self.composite_if_necessary(CompositingReason::Animation);
+                            return;
+                        }
+                    }
+                }
+
+                // If a fling is driving this event, peek the pre-scroll offset so we can
+                // tell afterwards which axis (if either) got clamped against the edge of
+                // the scrollable area.
+                let previous_offset = if combined_event.is_fling {
+                    details
+                        .scroll_tree
+                        .scroll_node_or_ancestor(
+                            &result.scroll_tree_node,
+                            ScrollLocation::Delta(LayoutVector2D::zero()),
+                        )
+                        .map(|(_, offset)| offset)
+                } else {
+                    None
+                };
+
                 match details
                     .scroll_tree
                     .scroll_node_or_ancestor(&result.scroll_tree_node, scroll_location)
                 {
                     Some((external_id, offset)) => {
+                        if let (Some(fling), Some(previous_offset), ScrollLocation::Delta(delta)) =
+                            (self.fling.as_mut(), previous_offset, scroll_location)
+                        {
+                            // Don't let a fling keep pushing against an axis it's already
+                            // clamped on; the other axis (if any) keeps decelerating normally.
+                            let moved = offset - previous_offset;
+                            if delta.x != 0.0 && moved.x.abs() < delta.x.abs() * 0.5 {
+                                fling.velocity.x = 0.0;
+                            }
+                            if delta.y != 0.0 && moved.y.abs() < delta.y.abs() * 0.5 {
+                                fling.velocity.y = 0.0;
+                            }
+                        }
                         let scroll_origin = LayoutPoint::new(-offset.x, -offset.y);
                         txn.scroll_node_with_id(
                             scroll_origin,
@@ -1285,34 +2163,138 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
                             ScrollClamping::NoClamping,
                         );
                     },
-                    None => {},
+                    None => {
+                        // There was nowhere left to scroll this node. If a fling produced
+                        // this event, stop it rather than continuing to push against the
+                        // clamp every frame.
+                        if combined_event.is_fling {
+                            self.fling = None;
+                        }
+                    },
                 }
             }
             self.send_scroll_positions_to_layout_for_pipeline(&result.pipeline_id);
 
+            let mut reasons = RenderReasons::SCROLL;
             if combined_event.magnification != 1.0 {
                 let old_zoom = self.pinch_zoom_level();
                 self.set_pinch_zoom_level(old_zoom * combined_event.magnification);
                 txn.set_pinch_zoom(ZoomFactor::new(self.pinch_zoom_level()));
+                reasons.insert(RenderReasons::PINCH_ZOOM);
             }
-            txn.generate_frame();
+            self.generate_frame(&mut txn, reasons);
             self.webrender_api
                 .send_transaction(self.webrender_document, txn);
             self.waiting_for_results_of_scroll = true
         }
     }
 
+    /// Replace the set of compositor-sampled transform/opacity animations for `pipeline_id`
+    /// with `animations`, as sent by layout when it hands an animation off to the
+    /// compositor instead of driving it through script ticks.
+    fn set_compositor_animations(
+        &mut self,
+        pipeline_id: PipelineId,
+        animations: FnvHashMap<PropertyBindingId, CompositorAnimation>,
+    ) {
+        self.pipeline_details(pipeline_id).compositor_animations = animations;
+        self.composite_if_necessary(CompositingReason::Animation);
+    }
+
+    /// Sample every compositor-side transform/opacity animation at the current time,
+    /// pushing the interpolated values to WebRender in a single
+    /// `update_dynamic_properties` + `generate_frame` transaction. Removes animations
+    /// once they finish and notifies the constellation. Returns `true` if any animation
+    /// is still running, so the caller knows whether to request another composite.
+    fn sample_compositor_animations(&mut self) -> bool {
+        let now = precise_time_ns();
+        let mut transforms = Vec::new();
+        let mut floats = Vec::new();
+        let mut any_running = false;
+
+        for (pipeline_id, details) in self.pipeline_details.iter_mut() {
+            if details.compositor_animations.is_empty() {
+                continue;
+            }
+
+            let mut finished_property_ids = Vec::new();
+            for (property_id, animation) in details.compositor_animations.iter() {
+                let elapsed = now.saturating_sub(animation.start_time);
+                let linear_progress = if animation.duration == 0 {
+                    1.0
+                } else {
+                    (elapsed as f32 / animation.duration as f32).max(0.0).min(1.0)
+                };
+                let progress = animation.timing_function.at(linear_progress);
+
+                match animation.values {
+                    AnimatedPropertyValue::Transform(from, to) => {
+                        transforms.push(PropertyValue {
+                            key: PropertyBindingKey::new(*property_id),
+                            value: interpolate_transform(&from, &to, progress),
+                        });
+                    },
+                    AnimatedPropertyValue::Opacity(from, to) => {
+                        floats.push(PropertyValue {
+                            key: PropertyBindingKey::new(*property_id),
+                            value: from + (to - from) * progress,
+                        });
+                    },
+                }
+
+                if linear_progress >= 1.0 {
+                    finished_property_ids.push(*property_id);
+                } else {
+                    any_running = true;
+                }
+            }
+
+            for property_id in finished_property_ids {
+                details.compositor_animations.remove(&property_id);
+                if let Err(e) = self.constellation_chan.send(
+                    ConstellationMsg::NotifyCompositorAnimationFinished(*pipeline_id, property_id),
+                ) {
+                    warn!("Sending animation finished notification failed ({:?}).", e);
+                }
+            }
+        }
+
+        if !transforms.is_empty() || !floats.is_empty() {
+            let mut txn = Transaction::new();
+            txn.update_dynamic_properties(DynamicProperties {
+                transforms,
+                floats,
+                colors: Vec::new(),
+            });
+            self.generate_frame(&mut txn, RenderReasons::ANIMATED_PROPERTY);
+            self.webrender_api
+                .send_transaction(self.webrender_document, txn);
+        }
+
+        any_running
+    }
+
     /// If there are any animations running, dispatches appropriate messages to the constellation.
     fn process_animations(&mut self) {
         let mut pipeline_ids = vec![];
+        // Purely compositor-sampled transform/opacity animations never set
+        // `animations_running` (that flag is script/layout's signal that *it* needs a
+        // tick); track them separately so the window doesn't fall Idle mid-animation.
+        let mut compositor_animations_running = false;
         for (pipeline_id, pipeline_details) in &self.pipeline_details {
             if (pipeline_details.animations_running || pipeline_details.animation_callbacks_running) &&
                 pipeline_details.visible
             {
                 pipeline_ids.push(*pipeline_id);
             }
+            if !pipeline_details.compositor_animations.is_empty() {
+                compositor_animations_running = true;
+            }
         }
-        let animation_state = if pipeline_ids.is_empty() && !self.webxr_main_thread.running() {
+        let animation_state = if pipeline_ids.is_empty() &&
+            !compositor_animations_running &&
+            !self.webxr_main_thread.running()
+        {
             windowing::AnimationState::Idle
         } else {
             windowing::AnimationState::Animating
@@ -1385,6 +2367,7 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
 
         let mut txn = Transaction::new();
         txn.set_page_zoom(page_zoom);
+        self.generate_frame(&mut txn, RenderReasons::PAGE_ZOOM);
         self.webrender_api
             .send_transaction(self.webrender_document, txn);
     }
@@ -1396,7 +2379,9 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             scroll_location: ScrollLocation::Delta(Vector2D::zero()), // TODO: Scroll to keep the center in view?
             cursor: Point2D::new(-1, -1), // Make sure this hits the base layer.
             event_count: 1,
+            is_fling: false,
         });
+        self.request_scroll_composite();
     }
 
     fn send_scroll_positions_to_layout_for_pipeline(&self, pipeline_id: &PipelineId) {
@@ -1441,13 +2426,69 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         false
     }
 
+    /// Refresh `PipelineDetails::rendered_epoch` for every known pipeline from
+    /// WebRender's own rendered-document state. Called after every composite so
+    /// `pipelines_rendered_to_epochs` always reflects the frame that was just shown,
+    /// without a round trip to the constellation.
+    fn update_rendered_epochs(&mut self) {
+        for (id, details) in self.pipeline_details.iter_mut() {
+            if let Some(WebRenderEpoch(epoch)) = self
+                .webrender
+                .current_epoch(self.webrender_document, id.to_webrender())
+            {
+                details.rendered_epoch = Some(Epoch(epoch));
+            }
+        }
+    }
+
+    /// Synchronously check whether every pipeline named in `expected_epochs` has
+    /// already been rendered up to (at least) the given epoch, per
+    /// `update_rendered_epochs`. A pipeline missing from our own tracking (e.g. one
+    /// that hasn't painted a single frame yet) counts as not rendered. Used to answer
+    /// `Msg::ArePipelineEpochsRendered` so a reftest/screenshot harness can block on
+    /// a specific, already-submitted layout without racing the paint.
+    pub fn pipelines_rendered_to_epochs(&self, expected_epochs: &HashMap<PipelineId, Epoch>) -> bool {
+        expected_epochs.iter().all(|(id, expected_epoch)| {
+            self.pipeline_details
+                .get(id)
+                .and_then(|details| details.rendered_epoch)
+                .map_or(false, |rendered_epoch| rendered_epoch.0 >= expected_epoch.0)
+        })
+    }
+
+    /// Whether WebRender's last-rendered epoch for every pipeline already matches
+    /// the epoch that pipeline's most recent `SendDisplayList` requested. If any
+    /// pipeline hasn't been rendered up to its requested epoch yet, the output image
+    /// can't possibly be stable, so there's no point paying for a constellation round
+    /// trip to find that out.
+    fn all_pipeline_epochs_rendered(&self) -> bool {
+        self.pipeline_details.iter().all(|(id, details)| {
+            let requested_epoch = match details.requested_epoch {
+                Some(epoch) => epoch,
+                None => return true,
+            };
+            self.webrender
+                .current_epoch(self.webrender_document, id.to_webrender()) ==
+                Some(requested_epoch)
+        })
+    }
+
     /// Query the constellation to see if the current compositor
     /// output matches the current frame tree output, and if the
     /// associated script threads are idle.
     fn is_ready_to_paint_image_output(&mut self) -> Result<(), NotReadyToPaint> {
         match self.ready_to_save_state {
             ReadyState::Unknown => {
-                // Unsure if the output image is stable.
+                // Before paying for a constellation round trip, check locally whether
+                // WebRender has actually rendered every pipeline's latest display list.
+                // This removes a frame of delay from the common case where the image
+                // obviously isn't stable yet, tightening the stable-image wait.
+                if !self.all_pipeline_epochs_rendered() {
+                    return Err(NotReadyToPaint::WaitingOnConstellation);
+                }
+
+                // The epochs line up locally, but we still don't know whether script is
+                // idle, so fall back to the constellation for that confirmation.
 
                 // Collect the currently painted epoch of each pipeline that is
                 // complete (i.e. has *all* layers painted to the requested epoch).
@@ -1524,6 +2565,21 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         &mut self,
         target: CompositeTarget,
         rect: Option<Rect<f32, CSSPixel>>,
+    ) -> Result<Option<Image>, UnableToComposite> {
+        if target == CompositeTarget::FullPage {
+            return self.composite_full_page(rect);
+        }
+
+        match self.backend {
+            CompositorBackend::Gl => self.composite_specific_target_gl(target, rect),
+            CompositorBackend::Software => self.composite_specific_target_software(target, rect),
+        }
+    }
+
+    fn composite_specific_target_gl(
+        &mut self,
+        target: CompositeTarget,
+        rect: Option<Rect<f32, CSSPixel>>,
     ) -> Result<Option<Image>, UnableToComposite> {
         let size = self.embedder_coordinates.framebuffer.to_u32();
 
@@ -1545,9 +2601,16 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
 
         self.webrender.update();
 
+        if self.sample_compositor_animations() {
+            // At least one compositor-sampled animation is still running; make sure we
+            // come back around to sample it again on the next frame.
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+
         let wait_for_stable_image = match target {
             CompositeTarget::WindowAndPng | CompositeTarget::PngFile => true,
             CompositeTarget::Window => self.exit_after_load,
+            CompositeTarget::FullPage => unreachable!("handled by composite_specific_target"),
         };
 
         if wait_for_stable_image {
@@ -1578,12 +2641,13 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             _ => (),
         };
 
+        let render_reasons = mem::replace(&mut self.accumulated_render_reasons, RenderReasons::empty());
         profile(
             ProfilerCategory::Compositing,
             None,
             self.time_profiler_chan.clone(),
             || {
-                debug!("compositor: compositing");
+                debug!("compositor: compositing (render_reasons: {:?})", render_reasons);
 
                 let size =
                     DeviceIntSize::from_untyped(self.embedder_coordinates.framebuffer.to_untyped());
@@ -1595,6 +2659,10 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             },
         );
 
+        self.save_program_cache_if_necessary();
+        self.update_rendered_epochs();
+        self.start_pending_async_screenshots();
+
         // If there are pending paint metrics, we check if any of the painted epochs is
         // one of the ones that the paint metrics recorder is expecting . In that case,
         // we get the current time, inform the layout thread about it and remove the
@@ -1715,7 +2783,229 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         Ok(rv)
     }
 
+    /// The `CompositorBackend::Software` counterpart to `composite_specific_target_gl`.
+    /// Rasterizes onto a CPU-backed framebuffer on worker threads instead of a live GL
+    /// context, so there's no window to present to: `CompositeTarget::Window` is a no-op
+    /// and `CompositeTarget::WindowAndPng`/`PngFile` read back from the CPU surface.
+    #[cfg(feature = "software-compositor")]
+    fn composite_specific_target_software(
+        &mut self,
+        target: CompositeTarget,
+        rect: Option<Rect<f32, CSSPixel>>,
+    ) -> Result<Option<Image>, UnableToComposite> {
+        let size = self.embedder_coordinates.framebuffer.to_u32();
+
+        self.webrender.update();
+
+        if self.sample_compositor_animations() {
+            // At least one compositor-sampled animation is still running; make sure we
+            // come back around to sample it again on the next frame.
+            self.composite_if_necessary(CompositingReason::Animation);
+        }
+
+        let wait_for_stable_image = match target {
+            CompositeTarget::WindowAndPng | CompositeTarget::PngFile => true,
+            CompositeTarget::Window => self.exit_after_load,
+            CompositeTarget::FullPage => unreachable!("handled by composite_specific_target"),
+        };
+
+        if wait_for_stable_image {
+            if self.animations_active() {
+                self.process_animations();
+                return Err(UnableToComposite::NotReadyToPaintImage(
+                    NotReadyToPaint::AnimationsActive,
+                ));
+            }
+            if let Err(result) = self.is_ready_to_paint_image_output() {
+                return Err(UnableToComposite::NotReadyToPaintImage(result));
+            }
+        }
+
+        let render_reasons = mem::replace(&mut self.accumulated_render_reasons, RenderReasons::empty());
+        let wr_size = DeviceIntSize::from_untyped(self.embedder_coordinates.framebuffer.to_untyped());
+        let cpu_framebuffer = profile(
+            ProfilerCategory::Compositing,
+            None,
+            self.time_profiler_chan.clone(),
+            || {
+                debug!(
+                    "compositor: compositing on the software backend (render_reasons: {:?})",
+                    render_reasons
+                );
+                swgl::composite_to_cpu_framebuffer(&mut self.webrender, self.webrender_document, wr_size)
+            },
+        );
+
+        self.start_pending_async_screenshots();
+        self.update_rendered_epochs();
+
+        let (x, y, width, height) = match rect {
+            Some(rect) => {
+                let rect = self.device_pixels_per_page_px().transform_rect(&rect);
+                (
+                    rect.origin.x as u32,
+                    rect.origin.y as u32,
+                    rect.size.width as u32,
+                    rect.size.height as u32,
+                )
+            },
+            None => (0, 0, size.width, size.height),
+        };
+
+        let rv = match target {
+            CompositeTarget::Window => None,
+            CompositeTarget::WindowAndPng => {
+                let img = cpu_framebuffer.sub_image(x, y, width, height);
+                Some(Image {
+                    width: img.width(),
+                    height: img.height(),
+                    format: PixelFormat::RGB8,
+                    bytes: ipc::IpcSharedMemory::from_bytes(&img.bytes),
+                    id: None,
+                    cors_status: CorsStatus::Safe,
+                })
+            },
+            CompositeTarget::PngFile => {
+                profile(
+                    ProfilerCategory::ImageSaving,
+                    None,
+                    self.time_profiler_chan.clone(),
+                    || match self.output_file.as_ref() {
+                        Some(path) => {
+                            let img = cpu_framebuffer.sub_image(x, y, width, height);
+                            if let Err(e) = img.save_as_png(path) {
+                                error!("Failed to save {} ({}).", path, e);
+                            }
+                        },
+                        None => error!("No file specified."),
+                    },
+                );
+                None
+            },
+            CompositeTarget::FullPage => unreachable!("handled by composite_specific_target"),
+        };
+
+        self.composition_request = CompositionRequest::NoCompositingNecessary;
+
+        self.process_animations();
+        self.waiting_for_results_of_scroll = false;
+
+        Ok(rv)
+    }
+
+    #[cfg(not(feature = "software-compositor"))]
+    fn composite_specific_target_software(
+        &mut self,
+        _target: CompositeTarget,
+        _rect: Option<Rect<f32, CSSPixel>>,
+    ) -> Result<Option<Image>, UnableToComposite> {
+        warn!("This build was not compiled with the \"software-compositor\" feature.");
+        Err(UnableToComposite::NotReadyToPaintImage(
+            NotReadyToPaint::WaitingOnConstellation,
+        ))
+    }
+
+    /// Compose the page in bands the height of the viewport, scrolling the root
+    /// scrollable node between each one, and stitch the bands into a single image
+    /// spanning the page's full scrollable height. Used for `CompositeTarget::FullPage`.
+    /// Each band is composited (and gated on `ready_to_save_state`) the same way a
+    /// normal `WindowAndPng` capture is; we just do it once per band and move the
+    /// scroll offset in between, restoring it once we're done.
+    fn composite_full_page(
+        &mut self,
+        rect: Option<Rect<f32, CSSPixel>>,
+    ) -> Result<Option<Image>, UnableToComposite> {
+        let not_ready = UnableToComposite::NotReadyToPaintImage(NotReadyToPaint::WaitingOnConstellation);
+
+        let pipeline_id = self.root_pipeline.id.ok_or(not_ready)?;
+        let viewport_height =
+            self.embedder_coordinates.viewport.size.height as f32 / self.device_pixels_per_page_px().get();
+
+        let (external_id, original_offset, page_height) = {
+            let details = self.pipeline_details.get(&pipeline_id).ok_or(not_ready)?;
+            match details.scroll_tree.nodes.first() {
+                Some(node) => match node.external_id() {
+                    Some(external_id) => {
+                        let page_height = node.scrollable_size().height + viewport_height;
+                        (
+                            external_id,
+                            node.offset().unwrap_or_else(LayoutVector2D::zero),
+                            page_height,
+                        )
+                    },
+                    // The root frame isn't scrollable; a single band covers the whole page.
+                    None => return self.composite_specific_target(CompositeTarget::WindowAndPng, rect),
+                },
+                None => return self.composite_specific_target(CompositeTarget::WindowAndPng, rect),
+            }
+        };
+
+        // Overlap each band by a couple of CSS pixels so that sub-pixel differences in
+        // where WebRender settles the scroll offset don't leave a hairline seam.
+        const BAND_OVERLAP: f32 = 2.0;
+        let step = (viewport_height - BAND_OVERLAP).max(1.0);
+        let max_offset = (page_height - viewport_height).max(0.0);
+
+        let mut bands = Vec::new();
+        let mut offset_y = 0.0f32;
+        loop {
+            let clamped_offset = offset_y.min(max_offset).max(0.0);
+            self.scroll_root_node_to_sync(pipeline_id, external_id, clamped_offset);
+            let band = self
+                .composite_specific_target(CompositeTarget::WindowAndPng, rect)?
+                .ok_or(not_ready)?;
+            bands.push((clamped_offset, band));
+            if clamped_offset >= max_offset {
+                break;
+            }
+            offset_y += step;
+        }
+
+        self.scroll_root_node_to_sync(pipeline_id, external_id, original_offset.y);
+
+        let device_pixels_per_page_px = self.device_pixels_per_page_px().get();
+        Ok(Some(stitch_bands(&bands, page_height, device_pixels_per_page_px)))
+    }
+
+    /// Synchronously scroll the root scrollable node of `pipeline_id` to `offset_y`
+    /// (in CSS pixels from the top of the page), blocking until WebRender's resulting
+    /// frame has arrived. Used to step through the page band by band for
+    /// `CompositeTarget::FullPage`, where each band must reflect the new scroll
+    /// position before we composite it.
+    fn scroll_root_node_to_sync(
+        &mut self,
+        pipeline_id: PipelineId,
+        external_id: ExternalScrollId,
+        offset_y: f32,
+    ) {
+        if let Some(details) = self.pipeline_details.get_mut(&pipeline_id) {
+            for node in details.scroll_tree.nodes.iter_mut() {
+                if node.external_id() == Some(external_id) {
+                    node.set_offset(LayoutVector2D::new(0.0, offset_y));
+                }
+            }
+        }
+
+        let mut txn = Transaction::new();
+        txn.scroll_node_with_id(
+            LayoutPoint::new(0.0, -offset_y),
+            external_id,
+            ScrollClamping::NoClamping,
+        );
+        self.generate_frame(&mut txn, RenderReasons::SCREENSHOT);
+        self.webrender_api
+            .send_transaction(self.webrender_document, txn);
+        self.waiting_for_results_of_scroll = true;
+
+        while self.waiting_for_results_of_scroll && self.shutdown_state != ShutdownState::ShuttingDown {
+            let msg = self.port.recv_compositor_msg();
+            self.handle_browser_message(msg);
+        }
+    }
+
     fn composite_if_necessary(&mut self, reason: CompositingReason) {
+        self.accumulated_render_reasons
+            .insert(reason.as_render_reasons());
         if self.composition_request == CompositionRequest::NoCompositingNecessary {
             if self.is_running_problem_test {
                 println!("updating composition_request ({:?})", reason);
@@ -1770,6 +3060,81 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         );
     }
 
+    /// Load precompiled shader program binaries from the on-disk program cache, if
+    /// one is configured, and hand them to WebRender so it can skip recompiling them.
+    fn load_program_cache(&mut self) {
+        let Some(cache) = self.program_cache.as_ref() else {
+            return;
+        };
+        let path = cache.path_for(&*self.webrender_gl);
+        match fs::read(&path) {
+            Ok(bytes) => {
+                if let Err(e) = self.webrender.load_program_cache(bytes) {
+                    warn!(
+                        "Failed to load program cache from {} ({:?}).",
+                        path.display(),
+                        e
+                    );
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => warn!(
+                "Failed to read program cache from {} ({:?}).",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    /// Write back any newly compiled shader programs after the first successful
+    /// frame. Happens once per process, plus once more after each
+    /// `clear_program_cache`; there's no benefit to re-saving an unchanged cache
+    /// on every subsequent frame.
+    fn save_program_cache_if_necessary(&mut self) {
+        if self.program_cache_saved {
+            return;
+        }
+        self.program_cache_saved = true;
+        let Some(cache) = self.program_cache.as_ref() else {
+            return;
+        };
+        let path = cache.path_for(&*self.webrender_gl);
+        match self.webrender.save_program_cache() {
+            Ok(bytes) => {
+                if let Some(parent) = path.parent() {
+                    if let Err(e) = create_dir_all(parent) {
+                        warn!("Failed to create program cache directory ({:?}).", e);
+                        return;
+                    }
+                }
+                if let Err(e) = fs::write(&path, bytes) {
+                    warn!(
+                        "Failed to write program cache to {} ({:?}).",
+                        path.display(),
+                        e
+                    );
+                }
+            },
+            Err(e) => warn!("Failed to serialize program cache ({:?}).", e),
+        }
+    }
+
+    /// Invalidate the on-disk program cache, e.g. because the GL driver version
+    /// changed since it was written. The file is removed, and the next successful
+    /// frame writes it out again (see `save_program_cache_if_necessary`).
+    pub fn clear_program_cache(&mut self) {
+        let Some(cache) = self.program_cache.as_ref() else {
+            return;
+        };
+        let path = cache.path_for(&*self.webrender_gl);
+        if let Err(e) = fs::remove_file(&path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to clear program cache at {} ({:?}).", path.display(), e);
+            }
+        }
+        self.program_cache_saved = false;
+    }
+
     pub fn receive_messages(&mut self) -> bool {
         // Check for new messages coming from the other threads in the system.
         let mut compositor_messages = vec![];
@@ -1802,9 +3167,17 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
             self.zoom_action = false;
         }
 
+        let mut scroll_timeout_pending = false;
         match self.composition_request {
             CompositionRequest::NoCompositingNecessary => {},
             CompositionRequest::CompositeNow(_) => self.composite(),
+            CompositionRequest::CompositeOnScrollTimeout(deadline) => {
+                if precise_time_ns() >= deadline {
+                    self.composition_request = CompositionRequest::NoCompositingNecessary;
+                } else {
+                    scroll_timeout_pending = true;
+                }
+            },
         }
 
         // Run the WebXR main thread
@@ -1813,7 +3186,18 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         // The WebXR thread may make a different context current
         let _ = self.webrender_surfman.make_gl_context_current();
 
-        if !self.pending_scroll_zoom_events.is_empty() && !self.waiting_for_results_of_scroll {
+        if self.fling.is_some() {
+            self.tick_fling();
+        }
+
+        if self.scroll_animation.is_some() {
+            self.tick_scroll_animation();
+        }
+
+        if !self.pending_scroll_zoom_events.is_empty() &&
+            !self.waiting_for_results_of_scroll &&
+            !scroll_timeout_pending
+        {
             self.process_pending_scroll_events()
         }
         self.shutdown_state != ShutdownState::FinishedShuttingDown
@@ -1870,55 +3254,295 @@ impl<Window: WindowMethods + ?Sized> IOCompositor<Window> {
         self.webrender.set_debug_flags(flags);
 
         let mut txn = Transaction::new();
-        txn.generate_frame();
+        self.generate_frame(&mut txn, RenderReasons::APPLICATION);
         self.webrender_api
             .send_transaction(self.webrender_document, txn);
     }
 
+    /// Enable or disable WebRender's transaction logging, which dumps every
+    /// scene-build/frame transaction to its log. Driven by the same keyboard
+    /// shortcut/devtools message as the other `toggle_webrender_debug` options, but
+    /// kept as its own method since transaction logging is a `DebugCommand` sent
+    /// straight to the render backend rather than a `DebugFlags` bit.
+    pub fn set_transaction_logging(&mut self, enabled: bool) {
+        self.webrender_api
+            .send_debug_cmd(DebugCommand::SetTransactionLogging(enabled));
+    }
+
+    /// Which `CaptureBits` a `SERVO_WR_CAPTURE_BITS` value (a comma-separated list of
+    /// `scene`, `frame`, `tile-cache`, `all`) names. Unrecognized names are warned
+    /// about and otherwise ignored, rather than rejecting the whole value, so a typo
+    /// doesn't silently disable capture altogether. An empty/unset result defaults to
+    /// `CaptureBits::all()`, matching the previous hard-coded behavior.
+    fn parse_capture_bits(value: &str) -> CaptureBits {
+        let mut bits = CaptureBits::empty();
+        for name in value.split(',') {
+            match name.trim() {
+                "scene" => bits.insert(CaptureBits::SCENE),
+                "frame" => bits.insert(CaptureBits::FRAME),
+                "tile-cache" => bits.insert(CaptureBits::TILE_CACHE),
+                "all" => bits.insert(CaptureBits::all()),
+                "" => {},
+                other => warn!("Unrecognized SERVO_WR_CAPTURE_BITS value {:?}; ignoring", other),
+            }
+        }
+        if bits.is_empty() {
+            CaptureBits::all()
+        } else {
+            bits
+        }
+    }
+
     pub fn capture_webrender(&mut self) {
+        let bits = env::var("SERVO_WR_CAPTURE_BITS")
+            .ok()
+            .map(|value| Self::parse_capture_bits(&value))
+            .unwrap_or_else(CaptureBits::all);
+
         let capture_id = now().to_timespec().sec.to_string();
-        let available_path = [env::current_dir(), Ok(env::temp_dir())]
-            .iter()
-            .filter_map(|val| {
-                val.as_ref()
-                    .map(|dir| dir.join("capture_webrender").join(&capture_id))
-                    .ok()
-            })
-            .find(|val| match create_dir_all(&val) {
+
+        // An explicit `SERVO_WR_CAPTURE_DIR` is tried first; if it can't be created
+        // (e.g. a restricted system), fall back through the same candidates as
+        // before rather than giving up outright.
+        let mut candidate_roots = Vec::new();
+        if let Some(configured_dir) = env::var_os("SERVO_WR_CAPTURE_DIR") {
+            candidate_roots.push(PathBuf::from(configured_dir));
+        }
+        if let Ok(dir) = env::current_dir() {
+            candidate_roots.push(dir);
+        }
+        candidate_roots.push(env::temp_dir());
+
+        let available_path = candidate_roots
+            .into_iter()
+            .map(|dir| dir.join("capture_webrender").join(&capture_id))
+            .find(|path| match create_dir_all(path) {
                 Ok(_) => true,
                 Err(err) => {
-                    eprintln!("Unable to create path '{:?}' for capture: {:?}", &val, err);
+                    eprintln!("Unable to create path '{:?}' for capture: {:?}", path, err);
                     false
                 },
             });
 
         match available_path {
             Some(capture_path) => {
-                let revision_file_path = capture_path.join("wr.txt");
-
                 debug!(
-                    "Trying to save webrender capture under {:?}",
-                    &revision_file_path
+                    "Using capture path {:?} with bits {:?}",
+                    capture_path, bits
                 );
-                self.webrender_api
-                    .save_capture(capture_path, CaptureBits::all());
+                self.capture_webrender_to(capture_path, bits);
+            },
+            None => eprintln!("Unable to locate path to save captures"),
+        }
+    }
 
-                match File::create(revision_file_path) {
-                    Ok(mut file) => {
-                        let revision = include!(concat!(env!("OUT_DIR"), "/webrender_revision.rs"));
-                        if let Err(err) = write!(&mut file, "{}", revision) {
-                            eprintln!("Unable to write webrender revision: {:?}", err)
-                        }
+    /// Serialize the current display lists, scene, spatial/scroll tree, and
+    /// resource cache (as selected by `bits`) to `path`, for offline debugging
+    /// of rendering regressions. This is the entry point used by
+    /// `Msg::CaptureWebRender`; `capture_webrender` is a convenience wrapper
+    /// that picks a path under `SERVO_WR_CAPTURE_DIR`, the current directory or
+    /// the system temp directory, whichever can be created first.
+    pub fn capture_webrender_to(&mut self, path: PathBuf, bits: CaptureBits) {
+        let revision_file_path = path.join("wr.txt");
+
+        debug!("Trying to save webrender capture under {:?}", &path);
+        self.webrender_api.save_capture(path, bits);
+
+        match File::create(revision_file_path) {
+            Ok(mut file) => {
+                let revision = include!(concat!(env!("OUT_DIR"), "/webrender_revision.rs"));
+                if let Err(err) = write!(&mut file, "{}", revision) {
+                    eprintln!("Unable to write webrender revision: {:?}", err)
+                }
+            },
+            Err(err) => eprintln!(
+                "Capture triggered, creating webrender revision info skipped: {:?}",
+                err
+            ),
+        }
+    }
+
+    /// Load a previously-saved WebRender capture directory (as produced by
+    /// `capture_webrender_to`) back into this compositor's document, so that a
+    /// headless `IOCompositor` can reproduce a rendering bug from the on-disk
+    /// artifact alone, without the rest of the browser stack. Any scene or
+    /// frame state captured here replaces whatever this document was
+    /// previously displaying.
+    pub fn load_webrender_capture(&mut self, path: PathBuf) {
+        self.webrender_api.load_capture(path);
+        self.composite_if_necessary(CompositingReason::Headless);
+    }
+
+    /// The `Msg::ReplayWebRender` counterpart to `capture_webrender_to`: validates
+    /// the `wr.txt` revision file written at capture time against the revision this
+    /// build was compiled against, then loads the capture through
+    /// `load_webrender_capture` regardless of the outcome (a mismatched revision
+    /// often still replays close enough to reproduce a bug, so we warn rather than
+    /// refuse).
+    pub fn replay_webrender(&mut self, path: PathBuf) {
+        let revision_file_path = path.join("wr.txt");
+        let current_revision = include!(concat!(env!("OUT_DIR"), "/webrender_revision.rs"));
+        let current_revision = format!("{}", current_revision);
+        match fs::read_to_string(&revision_file_path) {
+            Ok(captured_revision) => {
+                if captured_revision.trim() != current_revision.trim() {
+                    warn!(
+                        "Replaying a capture taken with WebRender revision {:?}, but this build is \
+                         revision {:?}; replay may not be faithful.",
+                        captured_revision.trim(),
+                        current_revision.trim()
+                    );
+                }
+            },
+            Err(e) => warn!(
+                "Could not read {:?} to validate capture revision ({:?}); replaying anyway.",
+                revision_file_path, e
+            ),
+        }
+
+        self.load_webrender_capture(path);
+    }
+
+    /// Read back the currently composited framebuffer and encode it as requested by
+    /// `Msg::CapturePixels`, without writing anything to disk or exiting the browser
+    /// (unlike `CompositeTarget::PngFile`, which is reftest-only). Returns `None` if
+    /// we aren't ready to composite or have no GL context (e.g. headless without
+    /// the `gl` feature).
+    #[cfg(feature = "gl")]
+    fn capture_composited_frame(&mut self, format: CaptureImageFormat) -> Option<Vec<u8>> {
+        let image = match self.composite_specific_target(CompositeTarget::WindowAndPng, None) {
+            Ok(Some(image)) => image,
+            Ok(None) => return None,
+            Err(e) => {
+                info!("Error capturing composited frame: {:?}", e);
+                return None;
+            },
+        };
+
+        match format {
+            CaptureImageFormat::Raw => Some(image.bytes.to_vec()),
+            CaptureImageFormat::Png | CaptureImageFormat::Jpeg => {
+                let rgb_image = RgbImage::from_raw(image.width, image.height, image.bytes.to_vec())?;
+                let mut cursor = std::io::Cursor::new(Vec::new());
+                let encoded_format = match format {
+                    CaptureImageFormat::Jpeg => ImageFormat::Jpeg,
+                    _ => ImageFormat::Png,
+                };
+                match DynamicImage::ImageRgb8(rgb_image).write_to(&mut cursor, encoded_format) {
+                    Ok(()) => Some(cursor.into_inner()),
+                    Err(e) => {
+                        error!("Failed to encode captured frame: {:?}", e);
+                        None
                     },
-                    Err(err) => eprintln!(
-                        "Capture triggered, creating webrender revision info skipped: {:?}",
-                        err
-                    ),
                 }
             },
-            None => eprintln!("Unable to locate path to save captures"),
         }
     }
+
+    #[cfg(not(feature = "gl"))]
+    fn capture_composited_frame(&mut self, _format: CaptureImageFormat) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Schedule an asynchronous screenshot of `rect` (or the whole viewport if `None`)
+    /// to be read back during the next composite, without blocking the caller or
+    /// stalling the compositor on the GPU readback the way `CompositeTarget::WindowAndPng`
+    /// does. Poll the result with `map_screenshot`. The synchronous path is kept as-is
+    /// for headless `--output-file` runs, where we exit right after the single composite
+    /// anyway and there's no frame rate to protect.
+    pub fn request_screenshot(&mut self, rect: Option<Rect<f32, CSSPixel>>) -> AsyncScreenshotHandle {
+        let handle = AsyncScreenshotHandle(self.next_async_screenshot_handle);
+        self.next_async_screenshot_handle += 1;
+        self.async_screenshots
+            .insert(handle, AsyncScreenshotState::Requested(rect));
+        self.composite_if_necessary(CompositingReason::Screenshot);
+        handle
+    }
+
+    /// Poll an asynchronous screenshot requested with `request_screenshot`. Returns
+    /// `None` if the handle is unknown, hasn't been picked up by a composite yet, or
+    /// its pixel-buffer-object readback hasn't landed yet. Once an image is returned,
+    /// its entry is removed so the pixel buffer backing it can be recycled.
+    #[cfg(feature = "gl")]
+    pub fn map_screenshot(&mut self, handle: AsyncScreenshotHandle) -> Option<Image> {
+        if let Some(AsyncScreenshotState::Pending(pbo)) = self.async_screenshots.get(&handle) {
+            let image = gl::try_map_pbo_readback(&*self.webrender_gl, *pbo)?;
+            let image = Image {
+                width: image.width(),
+                height: image.height(),
+                format: PixelFormat::RGB8,
+                bytes: ipc::IpcSharedMemory::from_bytes(&image),
+                id: None,
+                cors_status: CorsStatus::Safe,
+            };
+            self.async_screenshots
+                .insert(handle, AsyncScreenshotState::Ready(image));
+        }
+
+        match self.async_screenshots.get(&handle) {
+            Some(AsyncScreenshotState::Ready(_)) => {
+                match self.async_screenshots.remove(&handle) {
+                    Some(AsyncScreenshotState::Ready(image)) => Some(image),
+                    _ => None,
+                }
+            },
+            _ => None,
+        }
+    }
+
+    #[cfg(not(feature = "gl"))]
+    pub fn map_screenshot(&mut self, _handle: AsyncScreenshotHandle) -> Option<Image> {
+        None
+    }
+
+    /// Kick off the pixel-buffer-object readback for every asynchronous screenshot
+    /// still waiting on a composite. Must be called after the frame has been rendered
+    /// to the framebuffer, so there's something to read back.
+    #[cfg(feature = "gl")]
+    fn start_pending_async_screenshots(&mut self) {
+        let framebuffer_size = self.embedder_coordinates.framebuffer.to_u32();
+        let dppx = self.device_pixels_per_page_px();
+
+        let pending_handles: Vec<AsyncScreenshotHandle> = self
+            .async_screenshots
+            .iter()
+            .filter_map(|(handle, state)| match state {
+                AsyncScreenshotState::Requested(_) => Some(*handle),
+                _ => None,
+            })
+            .collect();
+
+        for handle in pending_handles {
+            let rect = match self.async_screenshots.get(&handle) {
+                Some(AsyncScreenshotState::Requested(rect)) => *rect,
+                _ => continue,
+            };
+
+            let (x, y, width, height) = match rect {
+                Some(rect) => {
+                    let rect = dppx.transform_rect(&rect);
+                    let x = rect.origin.x as i32;
+                    let y =
+                        (framebuffer_size.height as f32 - rect.origin.y - rect.size.height) as i32;
+                    (x, y, rect.size.width as u32, rect.size.height as u32)
+                },
+                None => (0, 0, framebuffer_size.width, framebuffer_size.height),
+            };
+
+            let pbo = gl::begin_pbo_readback(
+                &*self.webrender_gl,
+                x,
+                y,
+                FramebufferUintLength::new(width),
+                FramebufferUintLength::new(height),
+            );
+            self.async_screenshots
+                .insert(handle, AsyncScreenshotState::Pending(pbo));
+        }
+    }
+
+    #[cfg(not(feature = "gl"))]
+    fn start_pending_async_screenshots(&mut self) {}
 }
 
 /// Why we performed a composite. This is used for debugging.
@@ -1946,4 +3570,31 @@ pub enum CompositingReason {
     NewWebRenderScrollFrame,
     /// The window has been resized and will need to be synchronously repainted.
     Resize,
+    /// An asynchronous screenshot was requested and needs a composite to kick off its
+    /// pixel-buffer-object readback.
+    Screenshot,
+}
+
+impl CompositingReason {
+    /// The `RenderReasons` flag(s) that best describe this reason. Folded into
+    /// `accumulated_render_reasons` whenever a composite is requested, so a composite
+    /// triggered by a reason that never itself calls `generate_frame` (e.g. a resize
+    /// or an incoming WebRender frame notification) still shows up in the per-frame
+    /// reason log the next time we actually composite.
+    fn as_render_reasons(self) -> RenderReasons {
+        match self {
+            CompositingReason::DelayedCompositeTimeout |
+            CompositingReason::Scroll |
+            CompositingReason::ContinueScroll |
+            CompositingReason::NewWebRenderScrollFrame => RenderReasons::SCROLL,
+            CompositingReason::Headless |
+            CompositingReason::NewFrameTree |
+            CompositingReason::NewPaintedBuffers |
+            CompositingReason::NewWebRenderFrame => RenderReasons::APPLICATION,
+            CompositingReason::Animation => RenderReasons::ANIMATED_PROPERTY,
+            CompositingReason::Zoom => RenderReasons::PAGE_ZOOM,
+            CompositingReason::Resize => RenderReasons::WINDOW_RESIZE,
+            CompositingReason::Screenshot => RenderReasons::SCREENSHOT,
+        }
+    }
 }
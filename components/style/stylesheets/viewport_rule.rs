@@ -21,10 +21,11 @@ use crate::values::computed::{Context, ToComputedValue};
 use crate::values::generics::length::LengthPercentageOrAuto;
 use crate::values::generics::NonNegative;
 use crate::values::specified::{self, NoCalcLength};
-use crate::values::specified::{NonNegativeLengthPercentageOrAuto, ViewportPercentageLength};
+use crate::values::specified::NonNegativeLengthPercentageOrAuto;
 use app_units::Au;
 use cssparser::CowRcStr;
 use cssparser::{parse_important, AtRuleParser, DeclarationListParser, DeclarationParser, Parser};
+use cssparser::ParserInput;
 use euclid::Size2D;
 use selectors::parser::SelectorParseErrorKind;
 use std::borrow::Cow;
@@ -36,8 +37,140 @@ use style_traits::viewport::{Orientation, UserZoom, ViewportConstraints, Zoom};
 use style_traits::{CssWriter, ParseError, PinchZoomFactor, StyleParseErrorKind, ToCss};
 
 /// Whether parsing and processing of `@viewport` rules is enabled.
+///
+/// Gated behind the `layout.viewport.enabled` preference (off by default)
+/// so a mobile embedder can opt in to `@viewport` and meta-viewport
+/// cascading at runtime, and so tests can exercise this code path without
+/// a recompile.
 pub fn enabled() -> bool {
-    false
+    static_prefs::pref!("layout.viewport.enabled")
+}
+
+/// https://drafts.csswg.org/css-round-display/#viewport-fit-descriptor
+///
+/// Controls whether the layout viewport is allowed to expand under a
+/// physical display cutout (a notch or rounded corner), and therefore
+/// whether `env(safe-area-inset-*)` resolves to the device's actual cutout
+/// geometry or to `0px`.
+#[allow(missing_docs)]
+#[cfg_attr(feature = "servo", derive(MallocSizeOf))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToShmem)]
+pub enum ViewportFit {
+    Auto,
+    Contain,
+    Cover,
+}
+
+impl Default for ViewportFit {
+    fn default() -> Self {
+        ViewportFit::Auto
+    }
+}
+
+impl ViewportFit {
+    fn parse<'i, 't>(input: &mut Parser<'i, 't>) -> Result<Self, ParseError<'i>> {
+        let location = input.current_source_location();
+        let ident = input.expect_ident()?;
+        Ok(match_ignore_ascii_case! { &ident,
+            "auto" => ViewportFit::Auto,
+            "contain" => ViewportFit::Contain,
+            "cover" => ViewportFit::Cover,
+            _ => return Err(location.new_custom_error(SelectorParseErrorKind::UnexpectedIdent(ident.clone()))),
+        })
+    }
+}
+
+impl ToCss for ViewportFit {
+    fn to_css<W>(&self, dest: &mut CssWriter<W>) -> fmt::Result
+    where
+        W: Write,
+    {
+        dest.write_str(match *self {
+            ViewportFit::Auto => "auto",
+            ViewportFit::Contain => "contain",
+            ViewportFit::Cover => "cover",
+        })
+    }
+}
+
+impl FromMeta for ViewportFit {
+    fn from_meta(value: &str) -> Option<ViewportFit> {
+        Some(match value {
+            v if v.eq_ignore_ascii_case("auto") => ViewportFit::Auto,
+            v if v.eq_ignore_ascii_case("contain") => ViewportFit::Contain,
+            v if v.eq_ignore_ascii_case("cover") => ViewportFit::Cover,
+            _ => return None,
+        })
+    }
+}
+
+/// https://drafts.csswg.org/css-viewport/#interactive-widget-section
+///
+/// Controls how the layout and visual viewports react when an on-screen
+/// virtual keyboard (or other interactive widget) is shown.
+#[allow(missing_docs)]
+#[cfg_attr(feature = "servo", derive(MallocSizeOf))]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ToShmem)]
+pub enum InteractiveWidget {
+    ResizesVisual,
+    ResizesContent,
+    OverlaysContent,
+}
+
+impl Default for InteractiveWidget {
+    fn default() -> Self {
+        InteractiveWidget::ResizesVisual
+    }
+}
+
+impl InteractiveWidget {
+    fn parse<'i, 't>(input: &mut Parser<'i, 't>) -> Result<Self, ParseError<'i>> {
+        let location = input.current_source_location();
+        let ident = input.expect_ident()?;
+        Ok(match_ignore_ascii_case! { &ident,
+            "resizes-visual" => InteractiveWidget::ResizesVisual,
+            "resizes-content" => InteractiveWidget::ResizesContent,
+            "overlays-content" => InteractiveWidget::OverlaysContent,
+            _ => return Err(location.new_custom_error(SelectorParseErrorKind::UnexpectedIdent(ident.clone()))),
+        })
+    }
+}
+
+impl ToCss for InteractiveWidget {
+    fn to_css<W>(&self, dest: &mut CssWriter<W>) -> fmt::Result
+    where
+        W: Write,
+    {
+        dest.write_str(match *self {
+            InteractiveWidget::ResizesVisual => "resizes-visual",
+            InteractiveWidget::ResizesContent => "resizes-content",
+            InteractiveWidget::OverlaysContent => "overlays-content",
+        })
+    }
+}
+
+impl FromMeta for InteractiveWidget {
+    fn from_meta(value: &str) -> Option<InteractiveWidget> {
+        Some(match value {
+            v if v.eq_ignore_ascii_case("resizes-visual") => InteractiveWidget::ResizesVisual,
+            v if v.eq_ignore_ascii_case("resizes-content") => InteractiveWidget::ResizesContent,
+            v if v.eq_ignore_ascii_case("overlays-content") => InteractiveWidget::OverlaysContent,
+            _ => return None,
+        })
+    }
+}
+
+/// The four physical `env(safe-area-inset-*)` lengths, in app units. Read by
+/// computed-value resolution for the corresponding environment variables;
+/// see `Device::set_environment_safe_area_insets`. All-zero unless the
+/// cascaded `viewport-fit` is `cover`.
+#[allow(missing_docs)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SafeAreaInsets {
+    pub top: Au,
+    pub right: Au,
+    pub bottom: Au,
+    pub left: Au,
 }
 
 macro_rules! declare_viewport_descriptor {
@@ -92,6 +225,24 @@ macro_rules! declare_viewport_descriptor_inner {
                     )*
                 }
             }
+
+            /// The canonical `@viewport` descriptor name, e.g. `"min-width"`.
+            pub fn name(&self) -> &'static str {
+                match *self {
+                    $(
+                        ViewportDescriptor::$assigned_variant(..) => $assigned_variant_name,
+                    )*
+                }
+            }
+
+            /// The serialized value of this descriptor, without its name.
+            pub fn value_to_css_string(&self) -> String {
+                match *self {
+                    $(
+                        ViewportDescriptor::$assigned_variant(ref val) => val.to_css_string(),
+                    )*
+                }
+            }
         }
 
         impl ToCss for ViewportDescriptor {
@@ -127,6 +278,9 @@ declare_viewport_descriptor! {
 
     "user-zoom" => UserZoom(UserZoom),
     "orientation" => Orientation(Orientation),
+
+    "viewport-fit" => ViewportFit(ViewportFit),
+    "interactive-widget" => InteractiveWidget(InteractiveWidget),
 }
 
 trait FromMeta: Sized {
@@ -156,12 +310,13 @@ impl FromMeta for ViewportLength {
         }
 
         Some(match value {
-            v if v.eq_ignore_ascii_case("device-width") => specified!(
-                NoCalcLength::ViewportPercentage(ViewportPercentageLength::Vw(100.))
-            ),
-            v if v.eq_ignore_ascii_case("device-height") => specified!(
-                NoCalcLength::ViewportPercentage(ViewportPercentageLength::Vh(100.))
-            ),
+            // DEVICE-ADAPT § 9.4 legacy-compat translation: `device-width`
+            // and `device-height` don't map to a 100vw/100vh length (that
+            // would just be the initial viewport again); they mean "extend
+            // to the zoom level", exactly like the `extend-to-zoom` value
+            // this legacy syntax was modeled on.
+            v if v.eq_ignore_ascii_case("device-width") => ViewportLength::ExtendToZoom,
+            v if v.eq_ignore_ascii_case("device-height") => ViewportLength::ExtendToZoom,
             _ => match value.parse::<f32>() {
                 Ok(n) if n >= 0. => specified!(NoCalcLength::from_px(n.max(1.).min(10000.))),
                 Ok(_) => return None,
@@ -295,6 +450,8 @@ impl<'a, 'b, 'i> DeclarationParser<'i> for ViewportRuleParser<'a, 'b> {
             "max-zoom" => ok!(MaxZoom(Zoom::parse)),
             "user-zoom" => ok!(UserZoom(UserZoom::parse)),
             "orientation" => ok!(Orientation(Orientation::parse)),
+            "viewport-fit" => ok!(ViewportFit(ViewportFit::parse)),
+            "interactive-widget" => ok!(InteractiveWidget(InteractiveWidget::parse)),
             _ => Err(input.new_custom_error(SelectorParseErrorKind::UnexpectedIdent(name.clone()))),
         }
     }
@@ -321,11 +478,34 @@ fn is_whitespace_separator_or_equals(c: &char) -> bool {
     WHITESPACE.contains(c) || SEPARATOR.contains(c) || *c == '='
 }
 
-impl ViewportRule {
+/// The inclusive range a resolved (non-auto) viewport length is clamped
+/// into, per DEVICE-ADAPT's length-clamping behavior.
+const MIN_RESOLVED_LENGTH_PX: f32 = 1.;
+const MAX_RESOLVED_LENGTH_PX: f32 = 10000.;
+
+/// The inclusive range a zoom factor is clamped into, per DEVICE-ADAPT's
+/// scale-clamping behavior.
+const MIN_ZOOM_FACTOR: f32 = 0.1;
+const MAX_ZOOM_FACTOR: f32 = 10.;
+
+#[inline]
+fn clamp_resolved_length(length: Au) -> Au {
+    Au::from_f32_px(
+        length
+            .to_f32_px()
+            .max(MIN_RESOLVED_LENGTH_PX)
+            .min(MAX_RESOLVED_LENGTH_PX),
+    )
+}
+
+#[inline]
+fn clamp_zoom_factor(zoom: f32) -> f32 {
+    zoom.max(MIN_ZOOM_FACTOR).min(MAX_ZOOM_FACTOR)
+}
+
+impl Parse for ViewportRule {
     /// Parse a single @viewport rule.
-    ///
-    /// TODO(emilio): This could use the `Parse` trait now.
-    pub fn parse<'i, 't>(
+    fn parse<'i, 't>(
         context: &ParserContext,
         input: &mut Parser<'i, 't>,
     ) -> Result<Self, ParseError<'i>> {
@@ -424,6 +604,12 @@ impl ViewportRule {
                     n if n.eq_ignore_ascii_case("user-scalable") => {
                         push!(UserZoom(UserZoom::from_meta))
                     },
+                    n if n.eq_ignore_ascii_case("viewport-fit") => {
+                        push!(ViewportFit(ViewportFit::from_meta))
+                    },
+                    n if n.eq_ignore_ascii_case("interactive-widget") => {
+                        push!(InteractiveWidget(InteractiveWidget::from_meta))
+                    },
                     _ => {},
                 }
             }
@@ -495,16 +681,67 @@ impl ViewportRule {
 
         Some((name, value))
     }
+
+    /// Returns the serialized value of a single descriptor, by its CSS name
+    /// (e.g. `"min-width"`), mirroring CSSOM's `getPropertyValue()`. Returns
+    /// `None` if the descriptor isn't present in this rule.
+    pub fn get_property_value(&self, name: &str) -> Option<String> {
+        self.declarations
+            .iter()
+            .find(|declaration| declaration.descriptor.name().eq_ignore_ascii_case(name))
+            .map(|declaration| declaration.descriptor.value_to_css_string())
+    }
+
+    /// Parses `value` as the descriptor named `name` and inserts it into (or
+    /// replaces it in) this rule's declarations, mirroring CSSOM's
+    /// `setProperty()`. Returns `Err(())` if `name` or `value` don't parse.
+    pub fn set_property(
+        &mut self,
+        context: &ParserContext,
+        name: &str,
+        value: &str,
+    ) -> Result<(), ()> {
+        let mut input = ParserInput::new(value);
+        let mut input = Parser::new(&mut input);
+        let mut parser = ViewportRuleParser { context };
+        let declarations = input
+            .parse_entirely(|input| parser.parse_value(name.to_owned().into(), input))
+            .map_err(|_| ())?;
+        for declaration in declarations {
+            let discriminant = declaration.descriptor.discriminant_value();
+            match self
+                .declarations
+                .iter()
+                .position(|d| d.descriptor.discriminant_value() == discriminant)
+            {
+                Some(index) => self.declarations[index] = declaration,
+                None => self.declarations.push(declaration),
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ToCssWithGuard for ViewportRule {
-    // Serialization of ViewportRule is not specced.
+    /// Serializes the rule's descriptors in the canonical order given by
+    /// DEVICE-ADAPT's descriptor table, including `!important`, so that
+    /// `ViewportRule::parse(&rule.to_css(...))` round-trips.
     fn to_css(&self, _guard: &SharedRwLockReadGuard, dest: &mut CssStringWriter) -> fmt::Result {
         dest.write_str("@viewport { ")?;
-        let mut iter = self.declarations.iter();
-        iter.next().unwrap().to_css(&mut CssWriter::new(dest))?;
-        for declaration in iter {
-            dest.write_str(" ")?;
+        let mut first = true;
+        for index in 0..VIEWPORT_DESCRIPTOR_VARIANTS {
+            let declaration = match self
+                .declarations
+                .iter()
+                .find(|d| d.descriptor.discriminant_value() == index)
+            {
+                Some(declaration) => declaration,
+                None => continue,
+            };
+            if !first {
+                dest.write_str(" ")?;
+            }
+            first = false;
             declaration.to_css(&mut CssWriter::new(dest))?;
         }
         dest.write_str(" }")
@@ -582,14 +819,29 @@ pub trait MaybeNew {
         device: &Device,
         rule: &ViewportRule,
         quirks_mode: QuirksMode,
+    ) -> Option<ViewportConstraints> {
+        Self::maybe_new_with_scaling_override(device, rule, quirks_mode, false)
+    }
+
+    /// Like `maybe_new`, but `ignore_scaling_constraints` mirrors WebKit's
+    /// `ViewportConfiguration::setCanIgnoreScalingConstraints`: when set,
+    /// the author's `min-zoom`/`max-zoom`/`user-zoom: fixed` are dropped
+    /// from the result so the user can always pinch-zoom, e.g. under an
+    /// accessibility preference that overrides restrictive viewports.
+    fn maybe_new_with_scaling_override(
+        device: &Device,
+        rule: &ViewportRule,
+        quirks_mode: QuirksMode,
+        ignore_scaling_constraints: bool,
     ) -> Option<ViewportConstraints>;
 }
 
 impl MaybeNew for ViewportConstraints {
-    fn maybe_new(
+    fn maybe_new_with_scaling_override(
         device: &Device,
         rule: &ViewportRule,
         quirks_mode: QuirksMode,
+        ignore_scaling_constraints: bool,
     ) -> Option<ViewportConstraints> {
         use std::cmp;
 
@@ -609,6 +861,8 @@ impl MaybeNew for ViewportConstraints {
 
         let mut user_zoom = UserZoom::Zoom;
         let mut orientation = Orientation::Auto;
+        let mut viewport_fit = ViewportFit::Auto;
+        let mut interactive_widget = InteractiveWidget::ResizesVisual;
 
         // collapse the list of declarations into descriptor values
         for declaration in &rule.declarations {
@@ -625,11 +879,20 @@ impl MaybeNew for ViewportConstraints {
 
                 ViewportDescriptor::UserZoom(value) => user_zoom = value,
                 ViewportDescriptor::Orientation(value) => orientation = value,
+                ViewportDescriptor::ViewportFit(value) => viewport_fit = value,
+                ViewportDescriptor::InteractiveWidget(value) => interactive_widget = value,
             }
         }
 
         // TODO: return `None` if all descriptors are either absent or initial value
 
+        // DEVICE-ADAPT zoom-factor clamping: every zoom factor is clamped
+        // to [0.1, 10.0] before it's used to resolve the min/max-zoom
+        // ordering or the final initial zoom below.
+        initial_zoom = initial_zoom.map(clamp_zoom_factor);
+        min_zoom = min_zoom.map(clamp_zoom_factor);
+        max_zoom = max_zoom.map(clamp_zoom_factor);
+
         macro_rules! choose {
             ($op:ident, $opta:expr, $optb:expr) => {
                 match ($opta, $optb) {
@@ -664,6 +927,27 @@ impl MaybeNew for ViewportConstraints {
         // DEVICE-ADAPT § 6.2.3 Resolve non-auto lengths to pixel lengths
         let initial_viewport = device.au_viewport_size();
 
+        // DEVICE-ADAPT § 8 Orientation
+        // http://dev.w3.org/csswg/css-device-adapt/#orientation
+        //
+        // 'portrait' and 'landscape' lock the initial viewport's axis
+        // extents so that height >= width (portrait) or width >= height
+        // (landscape), swapping them against the device's natural
+        // orientation if it disagrees. This has to happen before
+        // extend-to-zoom is resolved below, since 'extend-to-zoom' lengths
+        // are computed against these same extents; 'auto' leaves the
+        // device's natural orientation untouched.
+        let initial_viewport = match orientation {
+            Orientation::Auto => initial_viewport,
+            Orientation::Portrait if initial_viewport.width > initial_viewport.height => {
+                Size2D::new(initial_viewport.height, initial_viewport.width)
+            },
+            Orientation::Landscape if initial_viewport.width < initial_viewport.height => {
+                Size2D::new(initial_viewport.height, initial_viewport.width)
+            },
+            _ => initial_viewport,
+        };
+
         let mut conditions = RuleCacheConditions::default();
         let context = Context {
             // Note: DEVICE-ADAPT § 5. states that relative length values are
@@ -692,7 +976,7 @@ impl MaybeNew for ViewportConstraints {
         macro_rules! to_pixel_length {
             ($value:ident, $dimension:ident, $extend_to:ident => $auto_extend_to:expr) => {
                 if let Some($value) = $value {
-                    match *$value {
+                    let resolved = match *$value {
                         ViewportLength::Specified(ref length) => match *length {
                             LengthPercentageOrAuto::Auto => None,
                             LengthPercentageOrAuto::LengthPercentage(ref lop) => Some(
@@ -709,7 +993,11 @@ impl MaybeNew for ViewportConstraints {
                                 (a, b) => cmp::max(a, b),
                             }
                         },
-                    }
+                    };
+                    // DEVICE-ADAPT length clamping: every resolved
+                    // (non-auto) pixel length is clamped to [1px, 10000px]
+                    // before the min/max descriptors interact below.
+                    resolved.map(clamp_resolved_length)
                 } else {
                     None
                 }
@@ -771,16 +1059,62 @@ impl MaybeNew for ViewportConstraints {
             },
         });
 
+        // The final resolved size is itself a pixel length and gets the
+        // same [1px, 10000px] clamp as the min/max descriptors that fed it.
+        let width = clamp_resolved_length(width);
+        let height = clamp_resolved_length(height);
+
+        // https://drafts.csswg.org/css-round-display/#viewport-fit-descriptor
+        //
+        // `cover` is the only value that exposes the device's physical
+        // cutout geometry to `env(safe-area-inset-*)`; `auto` and `contain`
+        // both keep the environment variables at 0px so that content which
+        // doesn't opt in to the cutout doesn't reserve space for it.
+        let safe_area_insets = if viewport_fit == ViewportFit::Cover {
+            device.safe_area_insets()
+        } else {
+            SafeAreaInsets::default()
+        };
+        device.set_environment_safe_area_insets(safe_area_insets);
+
+        // DEVICE-ADAPT § 10 Modifying 'zoom'
+        //
+        // When no initial zoom was specified, derive it from how far the
+        // actual resolved width had to shrink or grow relative to the
+        // device's initial viewport, so that e.g. `width: device-width`
+        // without an explicit `zoom` still fits the content to the screen
+        // instead of silently rendering at 100%.
+        let initial_zoom = initial_zoom.unwrap_or_else(|| {
+            let auto_zoom = if width.to_f32_px() == 0. {
+                1.
+            } else {
+                initial_viewport.width.to_f32_px() / width.to_f32_px()
+            };
+            max!(min_zoom, min!(max_zoom, Some(auto_zoom))).unwrap_or(auto_zoom)
+        });
+
+        // Accessibility override: an embedder that lets the user ignore
+        // scaling constraints drops the author's min/max-zoom and
+        // user-zoom: fixed from the output, defaulting max-zoom back to the
+        // spec ceiling so pinch-zoom is never capped below it. The resolved
+        // `size` above is untouched.
+        let (min_zoom, max_zoom, user_zoom) = if ignore_scaling_constraints {
+            (None, Some(MAX_ZOOM_FACTOR), UserZoom::Zoom)
+        } else {
+            (min_zoom, max_zoom, user_zoom)
+        };
+
         Some(ViewportConstraints {
             size: Size2D::new(width.to_f32_px(), height.to_f32_px()),
 
-            // TODO: compute a zoom factor for 'auto' as suggested by DEVICE-ADAPT § 10.
-            initial_zoom: PinchZoomFactor::new(initial_zoom.unwrap_or(1.)),
+            initial_zoom: PinchZoomFactor::new(initial_zoom),
             min_zoom: min_zoom.map(PinchZoomFactor::new),
             max_zoom: max_zoom.map(PinchZoomFactor::new),
 
             user_zoom: user_zoom,
             orientation: orientation,
+            viewport_fit: viewport_fit,
+            interactive_widget: interactive_widget,
         })
     }
 }